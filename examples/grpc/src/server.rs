@@ -28,7 +28,8 @@ impl Greeter for MyGreeter {
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // very opinionated init of tracing, look as is source to make your own
-    axum_tracing_opentelemetry::tracing_subscriber_ext::init_subscribers()
+    // (the guard flushes & shuts down the opentelemetry providers when dropped)
+    let _guard = axum_tracing_opentelemetry::tracing_subscriber_ext::init_subscribers()
         .expect("init subscribers");
 
     let addr = "[::1]:50051".parse().unwrap();
@@ -1,13 +1,14 @@
 use axum::extract::Path;
 use axum::{response::IntoResponse, routing::get, BoxError, Router};
-use axum_tracing_opentelemetry::{opentelemetry_tracing_layer, response_with_trace_layer};
+use axum_tracing_opentelemetry::{response_with_trace_layer, OtelLayerBuilder};
 use serde_json::json;
 use std::net::SocketAddr;
 
 #[tokio::main]
 async fn main() -> Result<(), BoxError> {
     // very opinionated init of tracing, look as is source to make your own
-    axum_tracing_opentelemetry::tracing_subscriber_ext::init_subscribers()?;
+    // (the guard flushes & shuts down the opentelemetry providers when dropped)
+    let _guard = axum_tracing_opentelemetry::tracing_subscriber_ext::init_subscribers()?;
 
     let app = app();
     // run it
@@ -30,12 +31,16 @@ fn app() -> Router {
             get(proxy_handler).post(proxy_handler),
         )
         .route("/", get(index)) // request processed inside span
+        .route("/health", get(health)) // request processed without span / trace
         // include trace context as header into the response
         .layer(response_with_trace_layer())
-        // opentelemetry_tracing_layer setup `TraceLayer`,
+        // OtelLayerBuilder setup `TraceLayer`,
         // that is provided by tower-http so you have to add that as a dependency.
-        .layer(opentelemetry_tracing_layer())
-        .route("/health", get(health)) // request processed without span / trace
+        .layer(
+            OtelLayerBuilder::default()
+                .with_excluded_path("/health")
+                .build(),
+        )
 }
 
 async fn health() -> impl IntoResponse {
@@ -80,5 +85,4 @@ async fn shutdown_signal() {
     }
 
     tracing::warn!("signal received, starting graceful shutdown");
-    opentelemetry::global::shutdown_tracer_provider();
 }
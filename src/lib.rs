@@ -4,7 +4,14 @@ mod middleware;
 mod tools;
 
 pub use self::middleware::response_with_trace_layer;
+#[cfg(feature = "metrics")]
+pub use self::middleware::{opentelemetry_metrics_layer, OtelMetricsLayer, OtelMetricsMiddleware};
 pub use self::middleware::{opentelemetry_tracing_layer, opentelemetry_tracing_layer_grpc};
+pub use self::middleware::{
+    ClientIpStrategy, FailureClassification, OtelLayerBuilder, OtelMakeGrpcSpan, OtelMakeSpan,
+    OtelOnBodyChunk, OtelOnEos, OtelOnFailure, OtelOnGrpcFailure, OtelOnRequest, OtelOnResponse,
+    SemConvMode, SpanNameStrategy,
+};
 pub use self::tools::*;
 
 #[cfg(feature = "tracer")]
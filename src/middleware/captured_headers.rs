@@ -0,0 +1,169 @@
+use http::{header, HeaderMap, HeaderName};
+use tracing::Span;
+
+/// Headers never recorded with their value (replaced by [`REDACTED`]), unless
+/// [`OtelLayerBuilder::with_redacted_headers`] is called.
+///
+/// [`OtelLayerBuilder::with_redacted_headers`]: super::OtelLayerBuilder::with_redacted_headers
+pub(crate) const DEFAULT_REDACTED_HEADERS: [HeaderName; 4] = [
+    header::AUTHORIZATION,
+    header::PROXY_AUTHORIZATION,
+    header::COOKIE,
+    header::SET_COOKIE,
+];
+
+pub(crate) const REDACTED: &str = "[REDACTED]";
+
+/// Request & response headers recorded as span attributes
+/// `http.request.header.<name>` & `http.response.header.<name>`.
+#[derive(Clone, Debug)]
+pub(crate) struct CapturedHeaders {
+    request: Vec<CapturedHeader>,
+    response: Vec<CapturedHeader>,
+    redacted: Vec<HeaderName>,
+}
+
+#[derive(Clone, Debug)]
+struct CapturedHeader {
+    name: HeaderName,
+    field: String,
+}
+
+impl Default for CapturedHeaders {
+    fn default() -> Self {
+        Self {
+            request: vec![],
+            response: vec![],
+            redacted: DEFAULT_REDACTED_HEADERS.to_vec(),
+        }
+    }
+}
+
+impl CapturedHeaders {
+    pub(crate) fn add_request_headers<I>(&mut self, names: I)
+    where
+        I: IntoIterator<Item = HeaderName>,
+    {
+        add_headers(&mut self.request, "http.request.header", names)
+    }
+
+    pub(crate) fn add_response_headers<I>(&mut self, names: I)
+    where
+        I: IntoIterator<Item = HeaderName>,
+    {
+        add_headers(&mut self.response, "http.response.header", names)
+    }
+
+    pub(crate) fn set_redacted_headers<I>(&mut self, names: I)
+    where
+        I: IntoIterator<Item = HeaderName>,
+    {
+        self.redacted = names.into_iter().collect();
+    }
+
+    /// Add the headers listed (comma-separated) in the environment variables
+    /// `OTEL_INSTRUMENTATION_HTTP_SERVER_CAPTURE_REQUEST_HEADERS` and
+    /// `OTEL_INSTRUMENTATION_HTTP_SERVER_CAPTURE_RESPONSE_HEADERS`.
+    pub(crate) fn add_headers_from_env(&mut self) {
+        if let Some(names) =
+            read_header_names_from_env("OTEL_INSTRUMENTATION_HTTP_SERVER_CAPTURE_REQUEST_HEADERS")
+        {
+            tracing::debug!(target: "otel::setup", OTEL_INSTRUMENTATION_HTTP_SERVER_CAPTURE_REQUEST_HEADERS = ?names);
+            self.add_request_headers(names);
+        }
+        if let Some(names) =
+            read_header_names_from_env("OTEL_INSTRUMENTATION_HTTP_SERVER_CAPTURE_RESPONSE_HEADERS")
+        {
+            tracing::debug!(target: "otel::setup", OTEL_INSTRUMENTATION_HTTP_SERVER_CAPTURE_RESPONSE_HEADERS = ?names);
+            self.add_response_headers(names);
+        }
+    }
+
+    /// The names of the span's fields used to record the headers.
+    pub(crate) fn span_fields(&self) -> Vec<String> {
+        self.request
+            .iter()
+            .chain(self.response.iter())
+            .map(|h| h.field.clone())
+            .collect()
+    }
+
+    pub(crate) fn record_request_headers(&self, headers: &HeaderMap, span: &Span) {
+        self.record(&self.request, headers, span)
+    }
+
+    pub(crate) fn record_response_headers(&self, headers: &HeaderMap, span: &Span) {
+        self.record(&self.response, headers, span)
+    }
+
+    fn record(&self, captured: &[CapturedHeader], headers: &HeaderMap, span: &Span) {
+        for h in captured {
+            let mut values = headers.get_all(&h.name).iter().peekable();
+            if values.peek().is_none() {
+                continue;
+            }
+            if self.redacted.contains(&h.name) {
+                span.record(h.field.as_str(), REDACTED);
+            } else {
+                // tracing can not record an array, so multiple values are joined with `,`
+                // (as allowed for the same header by RFC 9110), it is lossy for the values
+                // containing a `,` (eg `Set-Cookie`)
+                let value = values
+                    .map(|v| String::from_utf8_lossy(v.as_bytes()))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                span.record(h.field.as_str(), value.as_str());
+            }
+        }
+    }
+}
+
+fn add_headers<I>(captured: &mut Vec<CapturedHeader>, prefix: &str, names: I)
+where
+    I: IntoIterator<Item = HeaderName>,
+{
+    for name in names {
+        if !captured.iter().any(|h| h.name == name) {
+            // `HeaderName` is always lowercase
+            let field = format!("{prefix}.{}", name.as_str());
+            captured.push(CapturedHeader { name, field });
+        }
+    }
+}
+
+fn read_header_names_from_env(key: &str) -> Option<Vec<HeaderName>> {
+    let names = std::env::var(key).ok()?;
+    let names = names
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .filter_map(|s| match HeaderName::from_bytes(s.as_bytes()) {
+            Ok(name) => Some(name),
+            Err(_) => {
+                tracing::warn!(target: "otel::setup", "ignore invalid header name '{s}' from {key}");
+                None
+            }
+        })
+        .collect();
+    Some(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert2::assert;
+
+    #[test]
+    fn test_read_header_names_from_env() {
+        let key = "TEST_READ_HEADER_NAMES_FROM_ENV";
+        assert!(read_header_names_from_env(key) == None);
+        std::env::set_var(key, " X-Request-Id ,content-type,,invalid header");
+        assert!(
+            read_header_names_from_env(key)
+                == Some(vec![
+                    HeaderName::from_static("x-request-id"),
+                    header::CONTENT_TYPE
+                ])
+        );
+    }
+}
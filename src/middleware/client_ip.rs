@@ -0,0 +1,321 @@
+use axum::extract::ConnectInfo;
+use http::{HeaderMap, Request};
+use ipnet::IpNet;
+use std::net::{IpAddr, SocketAddr};
+
+/// How to extract the client ip (`http.client_ip`, `client.address`) from the request.
+///
+/// The peer address is read from [`ConnectInfo`], so it requires using
+/// [`Router::into_make_service_with_connect_info`]. The legacy `http.client_ip` records the peer
+/// address with its port, the stable `client.address` records only its ip (and `client.port`
+/// its port).
+///
+/// The forwarding headers (`X-Forwarded-For`, `Forwarded`, `X-Real-IP`) can be set by any client,
+/// only the strategies with a list of trusted proxies should be used when the client ip matters
+/// (eg for abuse investigations): the headers are only read when the peer is a trusted proxy
+/// (never when the peer is unknown), and the entries appended by the trusted proxies are skipped.
+///
+/// [`Router::into_make_service_with_connect_info`]: axum::Router::into_make_service_with_connect_info
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum ClientIpStrategy {
+    /// The first (leftmost) entry of the `X-Forwarded-For` header, else the peer address.
+    ///
+    /// The header is not checked, so the client ip can be spoofed.
+    #[default]
+    XForwardedForOrConnectInfo,
+    /// Only the peer address, forwarding headers are ignored.
+    ConnectInfo,
+    /// The rightmost entry of the `X-Forwarded-For` header that is not a trusted proxy,
+    /// else the peer address.
+    RightmostXForwardedFor {
+        /// The networks of the reverse proxies (eg `10.0.0.0/8`).
+        trusted_proxies: Vec<IpNet>,
+    },
+    /// The rightmost `for=` of the [RFC 7239] `Forwarded` header that is not a trusted proxy,
+    /// else the peer address.
+    ///
+    /// [RFC 7239]: https://www.rfc-editor.org/rfc/rfc7239
+    Forwarded {
+        /// The networks of the reverse proxies (eg `10.0.0.0/8`).
+        trusted_proxies: Vec<IpNet>,
+    },
+    /// The `X-Real-IP` header, else the peer address.
+    XRealIp {
+        /// The networks of the reverse proxies (eg `10.0.0.0/8`).
+        trusted_proxies: Vec<IpNet>,
+    },
+}
+
+/// The client address resolved by a [`ClientIpStrategy`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum ClientAddress {
+    /// The ip (or the entry as is for [`ClientIpStrategy::XForwardedForOrConnectInfo`]) from the
+    /// forwarding headers.
+    Forwarded(String),
+    /// The peer address from [`ConnectInfo`].
+    Peer(SocketAddr),
+}
+
+impl ClientAddress {
+    /// The address without the port of the peer (`client.address`).
+    pub(crate) fn address(&self) -> String {
+        match self {
+            ClientAddress::Forwarded(ip) => ip.clone(),
+            ClientAddress::Peer(addr) => addr.ip().to_string(),
+        }
+    }
+
+    /// The port of the peer (`client.port`), unknown when forwarded.
+    pub(crate) fn port(&self) -> Option<u16> {
+        match self {
+            ClientAddress::Forwarded(_) => None,
+            ClientAddress::Peer(addr) => Some(addr.port()),
+        }
+    }
+}
+
+/// The legacy `http.client_ip`: the peer address includes its port.
+impl std::fmt::Display for ClientAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClientAddress::Forwarded(ip) => ip.fmt(f),
+            ClientAddress::Peer(addr) => addr.fmt(f),
+        }
+    }
+}
+
+impl ClientIpStrategy {
+    /// The client ip from the headers, else the peer address.
+    pub(crate) fn client_ip<B>(&self, req: &Request<B>) -> Option<ClientAddress> {
+        let peer = req
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| *addr);
+        let peer_ip = peer.map(|addr| addr.ip());
+        let headers = req.headers();
+        let from_headers = match self {
+            ClientIpStrategy::XForwardedForOrConnectInfo => {
+                // the entry is used as is (not checked)
+                return first_x_forwarded_for(headers)
+                    .map(|ip| ClientAddress::Forwarded(ip.to_string()))
+                    .or_else(|| peer.map(ClientAddress::Peer));
+            }
+            ClientIpStrategy::ConnectInfo => None,
+            ClientIpStrategy::RightmostXForwardedFor { trusted_proxies } => {
+                let ips = x_forwarded_for(headers).collect::<Vec<_>>();
+                rightmost_untrusted(peer_ip, &ips, trusted_proxies)
+            }
+            ClientIpStrategy::Forwarded { trusted_proxies } => {
+                let ips = forwarded_for(headers).collect::<Vec<_>>();
+                rightmost_untrusted(peer_ip, &ips, trusted_proxies)
+            }
+            ClientIpStrategy::XRealIp { trusted_proxies } => {
+                if is_trusted_peer(peer_ip, trusted_proxies) {
+                    headers
+                        .get("x-real-ip")
+                        .and_then(|v| v.to_str().ok())
+                        .and_then(parse_ip)
+                } else {
+                    None
+                }
+            }
+        };
+        from_headers
+            .map(|ip| ClientAddress::Forwarded(ip.to_string()))
+            .or_else(|| peer.map(ClientAddress::Peer))
+    }
+}
+
+/// Without [`ConnectInfo`] the peer is unknown, so it is not trusted (the headers are ignored).
+fn is_trusted_peer(peer: Option<IpAddr>, trusted_proxies: &[IpNet]) -> bool {
+    matches!(peer, Some(ip) if is_trusted(ip, trusted_proxies))
+}
+
+fn is_trusted(ip: IpAddr, trusted_proxies: &[IpNet]) -> bool {
+    trusted_proxies.iter().any(|net| net.contains(&ip))
+}
+
+/// Walk the chain of proxies from the right (the closest to the server), skipping the trusted
+/// proxies. `None` in `ips` is an entry that is not an ip (invalid, `unknown`, obfuscated),
+/// it stops the walk as nothing on its left can be trusted.
+fn rightmost_untrusted(
+    peer: Option<IpAddr>,
+    ips: &[Option<IpAddr>],
+    trusted_proxies: &[IpNet],
+) -> Option<IpAddr> {
+    if !is_trusted_peer(peer, trusted_proxies) {
+        return None;
+    }
+    let mut leftmost = None;
+    for ip in ips.iter().rev() {
+        let ip = (*ip)?;
+        if !is_trusted(ip, trusted_proxies) {
+            return Some(ip);
+        }
+        leftmost = Some(ip);
+    }
+    // every hop is a trusted proxy
+    leftmost
+}
+
+/// The first (leftmost) entry of the `X-Forwarded-For` header.
+fn first_x_forwarded_for(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get("x-forwarded-for")?.to_str().ok()?;
+    value.split(',').next().map(str::trim)
+}
+
+/// The entries of every `X-Forwarded-For` headers, from left to right.
+fn x_forwarded_for(headers: &HeaderMap) -> impl Iterator<Item = Option<IpAddr>> + '_ {
+    headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(parse_ip)
+}
+
+/// The `for=` of the elements of every `Forwarded` headers, from left to right.
+/// An element without `for=` is an unknown hop (`None`), like `for=unknown`.
+///
+/// eg `Forwarded: for=192.0.2.43, for="[2001:db8:cafe::17]:4711";proto=https, for=unknown`
+fn forwarded_for(headers: &HeaderMap) -> impl Iterator<Item = Option<IpAddr>> + '_ {
+    headers
+        .get_all(http::header::FORWARDED)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|element| {
+            element
+                .split(';')
+                .find_map(|pair| {
+                    let (name, value) = pair.split_once('=')?;
+                    name.trim()
+                        .eq_ignore_ascii_case("for")
+                        .then(|| parse_ip(value.trim().trim_matches('"')))
+                })
+                .flatten()
+        })
+}
+
+/// Parse an ip with an optional port: `192.0.2.43`, `192.0.2.43:47011`, `2001:db8::1`,
+/// `[2001:db8::1]`, `[2001:db8::1]:4711`.
+fn parse_ip(value: &str) -> Option<IpAddr> {
+    let value = value.trim();
+    if let Ok(ip) = value.parse::<IpAddr>() {
+        return Some(ip);
+    }
+    if let Ok(addr) = value.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    value
+        .strip_prefix('[')
+        .and_then(|v| v.strip_suffix(']'))
+        .and_then(|v| v.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert2::assert;
+    use rstest::*;
+
+    const PROXY: &str = "10.0.0.1:8080";
+
+    fn trusted() -> Vec<IpNet> {
+        vec!["10.0.0.0/8".parse().unwrap(), "fd00::/8".parse().unwrap()]
+    }
+
+    fn ip(value: &str) -> Option<IpAddr> {
+        Some(value.parse().unwrap())
+    }
+
+    fn request(peer: Option<&str>, headers: &[(&str, &str)]) -> Request<()> {
+        let mut builder = Request::builder();
+        for (key, value) in headers {
+            builder = builder.header(*key, *value);
+        }
+        let mut req = builder.body(()).unwrap();
+        if let Some(peer) = peer {
+            req.extensions_mut()
+                .insert(ConnectInfo(peer.parse::<SocketAddr>().unwrap()));
+        }
+        req
+    }
+
+    #[rstest]
+    #[case("192.0.2.43", ip("192.0.2.43"))]
+    #[case(" 192.0.2.43:47011 ", ip("192.0.2.43"))]
+    #[case("2001:db8::1", ip("2001:db8::1"))]
+    #[case("[2001:db8::1]", ip("2001:db8::1"))]
+    #[case("[2001:db8::1]:4711", ip("2001:db8::1"))]
+    #[case("unknown", None)]
+    #[case("_hidden", None)]
+    #[case("", None)]
+    fn test_parse_ip(#[case] value: &str, #[case] expected: Option<IpAddr>) {
+        assert!(parse_ip(value) == expected);
+    }
+
+    #[rstest]
+    #[case(&["for=192.0.2.43"], vec![ip("192.0.2.43")])]
+    #[case(&["For=\"[2001:db8:cafe::17]:4711\""], vec![ip("2001:db8:cafe::17")])]
+    #[case(&["for=192.0.2.60;proto=http;by=203.0.113.43"], vec![ip("192.0.2.60")])]
+    #[case(&["proto=https; for=\"192.0.2.43:47011\""], vec![ip("192.0.2.43")])]
+    #[case(&["for=192.0.2.43, for=unknown", "for=10.0.0.2"], vec![ip("192.0.2.43"), None, ip("10.0.0.2")])]
+    #[case(&["proto=https"], vec![None])]
+    #[case(&["for=192.0.2.43, proto=https, for=10.0.0.2"], vec![ip("192.0.2.43"), None, ip("10.0.0.2")])]
+    fn test_forwarded_for(#[case] values: &[&str], #[case] expected: Vec<Option<IpAddr>>) {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(http::header::FORWARDED, value.parse().unwrap());
+        }
+        assert!(forwarded_for(&headers).collect::<Vec<_>>() == expected);
+    }
+
+    #[rstest]
+    // legacy: first entry, spoofable
+    #[case(ClientIpStrategy::XForwardedForOrConnectInfo, Some(PROXY), &[("x-forwarded-for", "1.1.1.1, 192.0.2.43")], Some("1.1.1.1"))]
+    #[case(ClientIpStrategy::XForwardedForOrConnectInfo, Some("192.0.2.43:1234"), &[], Some("192.0.2.43:1234"))]
+    #[case(ClientIpStrategy::XForwardedForOrConnectInfo, None, &[], None)]
+    #[case(ClientIpStrategy::ConnectInfo, Some("[2001:db8::1]:1234"), &[("x-forwarded-for", "1.1.1.1")], Some("[2001:db8::1]:1234"))]
+    // rightmost untrusted
+    #[case(ClientIpStrategy::RightmostXForwardedFor { trusted_proxies: trusted() }, Some(PROXY), &[("x-forwarded-for", "1.1.1.1, 192.0.2.43, 10.0.0.2")], Some("192.0.2.43"))]
+    #[case(ClientIpStrategy::RightmostXForwardedFor { trusted_proxies: trusted() }, Some(PROXY), &[("x-forwarded-for", "10.0.0.3, 10.0.0.2")], Some("10.0.0.3"))]
+    #[case(ClientIpStrategy::RightmostXForwardedFor { trusted_proxies: trusted() }, Some(PROXY), &[("x-forwarded-for", "192.0.2.43, garbage, 10.0.0.2")], Some("10.0.0.1:8080"))]
+    #[case(ClientIpStrategy::RightmostXForwardedFor { trusted_proxies: trusted() }, Some(PROXY), &[], Some("10.0.0.1:8080"))]
+    #[case(ClientIpStrategy::RightmostXForwardedFor { trusted_proxies: trusted() }, Some("192.0.2.1:1234"), &[("x-forwarded-for", "1.1.1.1")], Some("192.0.2.1:1234"))]
+    // without peer, the headers are not trusted
+    #[case(ClientIpStrategy::RightmostXForwardedFor { trusted_proxies: trusted() }, None, &[("x-forwarded-for", "1.1.1.1, 10.0.0.2")], None)]
+    // forwarded
+    #[case(ClientIpStrategy::Forwarded { trusted_proxies: trusted() }, Some(PROXY), &[("forwarded", "for=1.1.1.1, for=\"[2001:db8::1]:4711\";proto=https, for=\"[fd00::2]\"")], Some("2001:db8::1"))]
+    #[case(ClientIpStrategy::Forwarded { trusted_proxies: trusted() }, Some("192.0.2.1:1234"), &[("forwarded", "for=1.1.1.1")], Some("192.0.2.1:1234"))]
+    #[case(ClientIpStrategy::Forwarded { trusted_proxies: trusted() }, Some(PROXY), &[("forwarded", "for=unknown")], Some("10.0.0.1:8080"))]
+    // x-real-ip
+    #[case(ClientIpStrategy::XRealIp { trusted_proxies: trusted() }, Some(PROXY), &[("x-real-ip", "192.0.2.43")], Some("192.0.2.43"))]
+    #[case(ClientIpStrategy::XRealIp { trusted_proxies: trusted() }, Some("192.0.2.1:1234"), &[("x-real-ip", "1.1.1.1")], Some("192.0.2.1:1234"))]
+    #[case(ClientIpStrategy::XRealIp { trusted_proxies: trusted() }, None, &[("x-real-ip", "1.1.1.1")], None)]
+    #[case(ClientIpStrategy::Forwarded { trusted_proxies: trusted() }, None, &[("forwarded", "for=1.1.1.1")], None)]
+    fn test_client_ip(
+        #[case] strategy: ClientIpStrategy,
+        #[case] peer: Option<&str>,
+        #[case] headers: &[(&str, &str)],
+        #[case] expected: Option<&str>,
+    ) {
+        let client_ip = strategy.client_ip(&request(peer, headers));
+        assert!(client_ip.map(|address| address.to_string()).as_deref() == expected);
+    }
+
+    #[rstest]
+    #[case(Some("192.0.2.43:1234"), &[], Some(("192.0.2.43", Some(1234))))]
+    #[case(Some("[2001:db8::1]:1234"), &[], Some(("2001:db8::1", Some(1234))))]
+    #[case(Some(PROXY), &[("x-forwarded-for", "192.0.2.43")], Some(("192.0.2.43", None)))]
+    #[case(None, &[], None)]
+    fn test_client_address_and_port(
+        #[case] peer: Option<&str>,
+        #[case] headers: &[(&str, &str)],
+        #[case] expected: Option<(&str, Option<u16>)>,
+    ) {
+        let client_ip = ClientIpStrategy::default().client_ip(&request(peer, headers));
+        let address_and_port = client_ip.map(|address| (address.address(), address.port()));
+        assert!(address_and_port.as_ref().map(|(a, p)| (a.as_str(), *p)) == expected);
+    }
+}
@@ -0,0 +1,221 @@
+//! Spans with a set of fields only known at runtime.
+//!
+//! `tracing` requires the fields of a span to be declared (as `'static`) at the callsite,
+//! so the `*_span!` macros can not be used when the names of the fields depend on the
+//! configuration (eg `http.request.header.<name>`). [`DynSpan`] registers a callsite
+//! (leaked, and shared by every layer built with the same set of fields, the number of callsites
+//! is bounded) that declares them.
+
+use std::collections::BTreeMap;
+use std::sync::{
+    atomic::{AtomicBool, AtomicU8, Ordering},
+    Mutex, OnceLock,
+};
+use tracing::{
+    callsite::{Callsite, Identifier},
+    field::FieldSet,
+    metadata::Kind,
+    subscriber::Interest,
+    Level, Metadata, Span, Value,
+};
+
+/// The maximum number of callsites with extra fields, the callsites are never freed so
+/// building layers with ever changing fields (eg dynamic header names) should not leak.
+const MAX_CALLSITES: usize = 64;
+
+/// The number of values set at creation is limited by older versions of `tracing`,
+/// the remaining values are recorded just after the creation.
+const MAX_VALUES_AT_CREATION: usize = 32;
+
+/// The callsites by name, target & sorted names of the fields.
+type Callsites = BTreeMap<(&'static str, &'static str, Vec<String>), &'static DynCallsite>;
+
+static CALLSITES: Mutex<Callsites> = Mutex::new(BTreeMap::new());
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct DynSpan {
+    callsite: &'static DynCallsite,
+}
+
+impl DynSpan {
+    /// `fields` & `extra_fields` should include every field that will be set or recorded on
+    /// the span. When too many callsites are already registered, the `extra_fields` are
+    /// ignored (with a warning).
+    pub(crate) fn new(
+        name: &'static str,
+        target: &'static str,
+        fields: &[&'static str],
+        extra_fields: &[String],
+    ) -> Self {
+        let mut names = fields
+            .iter()
+            .map(|f| f.to_string())
+            .chain(extra_fields.iter().cloned())
+            .collect::<Vec<_>>();
+        names.sort();
+        names.dedup();
+        let mut callsites = CALLSITES.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(cs) = callsites.get(&(name, target, names.clone())) {
+            return DynSpan { callsite: cs };
+        }
+        if !extra_fields.is_empty() && callsites.len() >= MAX_CALLSITES {
+            drop(callsites);
+            tracing::warn!(target: "otel::setup", "too many sets of fields for the span '{name}' (more than {MAX_CALLSITES}), the fields {extra_fields:?} are ignored");
+            return Self::new(name, target, fields, &[]);
+        }
+        let cs = DynCallsite::register(name, target, &names);
+        callsites.insert((name, target, names), cs);
+        DynSpan { callsite: cs }
+    }
+
+    /// Create an INFO span (like `tracing::info_span!`), `values` are set at creation
+    /// (values for fields not declared by the callsite are ignored, like with `Span::record`),
+    /// the other fields of the span are empty until recorded.
+    pub(crate) fn span(&self, values: &[(&str, &dyn Value)]) -> Span {
+        let meta = self.callsite.metadata();
+        if !self.is_enabled() {
+            return Span::none();
+        }
+        let values = values
+            .iter()
+            .filter_map(|(name, value)| meta.fields().field(*name).map(|field| (field, *value)))
+            .collect::<Vec<_>>();
+        let (at_creation, after_creation) =
+            values.split_at(values.len().min(MAX_VALUES_AT_CREATION));
+        let padding = meta
+            .fields()
+            .iter()
+            .next()
+            .expect("callsite declares at least one field");
+        let at_creation: [_; MAX_VALUES_AT_CREATION] =
+            std::array::from_fn(|i| match at_creation.get(i) {
+                Some((field, value)) => (field, Some(*value)),
+                None => (&padding, None),
+            });
+        if !after_creation.is_empty() && !self.callsite.warned.swap(true, Ordering::Relaxed) {
+            tracing::warn!(
+                "only {MAX_VALUES_AT_CREATION} values can be set at the creation of the span '{}', the others are recorded just after (so missing from the creation event)",
+                meta.name()
+            );
+        }
+        let span = Span::new(meta, &meta.fields().value_set(&at_creation));
+        for (field, value) in after_creation {
+            span.record(field, *value);
+        }
+        span
+    }
+
+    /// Whether the field is declared by the callsite.
+    pub(crate) fn has_field(&self, name: &str) -> bool {
+        self.callsite.metadata().fields().field(name).is_some()
+    }
+
+    fn is_enabled(&self) -> bool {
+        if tracing::level_filters::STATIC_MAX_LEVEL < Level::INFO
+            || tracing::level_filters::LevelFilter::current() < Level::INFO
+        {
+            return false;
+        }
+        let interest = self.callsite.interest();
+        !interest.is_never()
+            && (interest.is_always()
+                || tracing::dispatcher::get_default(|d| d.enabled(self.callsite.metadata())))
+    }
+}
+
+#[derive(Debug)]
+struct DynCallsite {
+    interest: AtomicU8,
+    /// the warning about the values not set at creation is logged once
+    warned: AtomicBool,
+    meta: OnceLock<Metadata<'static>>,
+}
+
+impl DynCallsite {
+    const INTEREST_NEVER: u8 = 0;
+    const INTEREST_SOMETIMES: u8 = 1;
+    const INTEREST_ALWAYS: u8 = 2;
+
+    fn register(name: &'static str, target: &'static str, fields: &[String]) -> &'static Self {
+        let names: &'static [&'static str] = Box::leak(
+            fields
+                .iter()
+                .map(|f| &*Box::leak(f.clone().into_boxed_str()))
+                .collect(),
+        );
+        let cs: &'static Self = Box::leak(Box::new(DynCallsite {
+            interest: AtomicU8::new(Self::INTEREST_SOMETIMES),
+            warned: AtomicBool::new(false),
+            meta: OnceLock::new(),
+        }));
+        let meta = Metadata::new(
+            name,
+            target,
+            Level::INFO,
+            Some(file!()),
+            None,
+            Some(target),
+            FieldSet::new(names, Identifier(cs)),
+            Kind::SPAN,
+        );
+        cs.meta.set(meta).expect("metadata set only once");
+        tracing::callsite::register(cs);
+        cs
+    }
+
+    fn interest(&self) -> Interest {
+        match self.interest.load(Ordering::Relaxed) {
+            Self::INTEREST_NEVER => Interest::never(),
+            Self::INTEREST_ALWAYS => Interest::always(),
+            _ => Interest::sometimes(),
+        }
+    }
+}
+
+impl Callsite for DynCallsite {
+    fn set_interest(&self, interest: Interest) {
+        let interest = if interest.is_never() {
+            Self::INTEREST_NEVER
+        } else if interest.is_always() {
+            Self::INTEREST_ALWAYS
+        } else {
+            Self::INTEREST_SOMETIMES
+        };
+        self.interest.store(interest, Ordering::SeqCst);
+    }
+
+    fn metadata(&self) -> &Metadata<'_> {
+        self.meta
+            .get()
+            .expect("metadata set before the callsite is registered")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert2::check;
+
+    #[test]
+    fn share_the_callsite_of_the_same_set_of_fields() {
+        let extra = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        let span = DynSpan::new("test", "dyn_span", &["a", "b"], &extra(&["x-1", "x-2"]));
+        let same = DynSpan::new(
+            "test",
+            "dyn_span",
+            &["b", "a"],
+            &extra(&["x-2", "x-1", "a"]),
+        );
+        let other = DynSpan::new("test", "dyn_span", &["a", "b"], &extra(&["x-1"]));
+        check!(std::ptr::eq(span.callsite, same.callsite));
+        check!(!std::ptr::eq(span.callsite, other.callsite));
+        let names = span
+            .callsite
+            .metadata()
+            .fields()
+            .iter()
+            .map(|f| f.name())
+            .collect::<Vec<_>>();
+        check!(names == ["a", "b", "x-1", "x-2"]);
+    }
+}
@@ -0,0 +1,115 @@
+use http::{HeaderMap, Method, Uri};
+use std::sync::Arc;
+
+/// Requests not traced by the layer (no span, no extraction of the remote context).
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Exclusions {
+    paths: Vec<String>,
+    routes: Vec<String>,
+    predicates: Vec<ExcludePredicate>,
+}
+
+#[derive(Clone)]
+struct ExcludePredicate(ExcludeFn);
+
+type ExcludeFn = Arc<dyn Fn(&Method, &Uri, &HeaderMap) -> bool + Send + Sync>;
+
+impl std::fmt::Debug for ExcludePredicate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ExcludePredicate(..)")
+    }
+}
+
+impl Exclusions {
+    pub(crate) fn add_path(&mut self, path: String) {
+        self.paths.push(path);
+    }
+
+    pub(crate) fn add_route(&mut self, route: String) {
+        self.routes.push(route);
+    }
+
+    pub(crate) fn add_predicate<F>(&mut self, predicate: F)
+    where
+        F: Fn(&Method, &Uri, &HeaderMap) -> bool + Send + Sync + 'static,
+    {
+        self.predicates.push(ExcludePredicate(Arc::new(predicate)));
+    }
+
+    /// Add the entries listed (comma-separated) in the environment variable `OTEL_RUST_EXCLUDED_URLS`,
+    /// each entry is used as a path and as a route.
+    pub(crate) fn add_from_env(&mut self) {
+        if let Some(entries) = read_excluded_urls_from_env("OTEL_RUST_EXCLUDED_URLS") {
+            tracing::debug!(target: "otel::setup", OTEL_RUST_EXCLUDED_URLS = ?entries);
+            for entry in entries {
+                self.add_route(entry.clone());
+                self.add_path(entry);
+            }
+        }
+    }
+
+    pub(crate) fn is_excluded(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        headers: &HeaderMap,
+        route: Option<&str>,
+    ) -> bool {
+        self.paths.iter().any(|p| path_matches(p, uri.path()))
+            || route.is_some_and(|route| self.routes.iter().any(|r| r == route))
+            || self.predicates.iter().any(|p| (p.0)(method, uri, headers))
+    }
+}
+
+/// `pattern` ending with `*` matches every path starting with the rest of the pattern.
+fn path_matches(pattern: &str, path: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => path.starts_with(prefix),
+        None => pattern == path,
+    }
+}
+
+fn read_excluded_urls_from_env(key: &str) -> Option<Vec<String>> {
+    let entries = std::env::var(key).ok()?;
+    let entries = entries
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect();
+    Some(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert2::assert;
+    use rstest::*;
+
+    #[rstest]
+    #[case("/health", "/health", true)]
+    #[case("/health", "/healthz", false)]
+    #[case("/health", "/", false)]
+    #[case("/internal/*", "/internal/metrics", true)]
+    #[case("/internal/*", "/internal/", true)]
+    #[case("/internal/*", "/internal", false)]
+    #[case("*", "/anything", true)]
+    fn test_path_matches(#[case] pattern: &str, #[case] path: &str, #[case] expected: bool) {
+        assert!(path_matches(pattern, path) == expected);
+    }
+
+    #[test]
+    fn test_read_excluded_urls_from_env() {
+        let key = "TEST_READ_EXCLUDED_URLS_FROM_ENV";
+        assert!(read_excluded_urls_from_env(key) == None);
+        std::env::set_var(key, "/health, /metrics,,/users/:id");
+        assert!(
+            read_excluded_urls_from_env(key)
+                == Some(vec![
+                    "/health".to_string(),
+                    "/metrics".to_string(),
+                    "/users/:id".to_string()
+                ])
+        );
+    }
+}
@@ -0,0 +1,305 @@
+//
+//! OpenTelemetry metrics middleware.
+//!
+//! See [`opentelemetry_metrics_layer`] for more details.
+
+use super::trace_extractor::{http_flavor, http_request_method, http_scheme};
+use axum::{body::HttpBody, extract::MatchedPath};
+use futures::future::BoxFuture;
+use http::{Request, Response};
+use opentelemetry::{
+    metrics::{Histogram, Meter, Unit, UpDownCounter},
+    Context as OtelContext, KeyValue,
+};
+use std::{
+    sync::Arc,
+    task::{Context, Poll},
+    time::Instant,
+};
+use tower::{Layer, Service};
+
+/// OpenTelemetry metrics middleware.
+///
+/// The instruments are created from the global meter provider (`opentelemetry::global::meter`),
+/// so the layer should be created after the installation of the meter provider
+/// (use [`OtelMetricsLayer::new`] to use a specific meter).
+///
+/// # Metrics
+///
+/// - `http.server.duration` (histogram, `ms`): The duration of the inbound requests,
+///   until the response is ready (the response body is not waited)
+/// - `http.server.active_requests` (up-down counter): The number of requests in flight
+/// - `http.server.request.size` (histogram, `By`): The size of the request body,
+///   when known (eg via `Content-Length`)
+/// - `http.server.response.size` (histogram, `By`): The size of the response body,
+///   when known
+///
+/// # Attributes
+///
+/// - `http.method`, `http.scheme`, `http.flavor`: On every metrics, the non-standard methods
+///   are recorded as `_OTHER` (to keep the cardinality bounded)
+/// - `http.route`: The matched route (never the path of the request, to keep the cardinality
+///   bounded), on every metrics except `http.server.active_requests`
+/// - `http.status_code`: On every metrics except `http.server.active_requests` and
+///   `http.server.request.size`
+///
+/// Like for [`opentelemetry_tracing_layer`], the route is only available when the layer is
+/// added via `Router::layer` (not via `Router::route_layer` or around the `Router`).
+///
+/// # Example
+///
+/// ```
+/// use axum::{Router, routing::get};
+/// use axum_tracing_opentelemetry::{opentelemetry_metrics_layer, opentelemetry_tracing_layer};
+///
+/// async fn handler() {}
+///
+/// let app = Router::new()
+///     .route("/", get(handler))
+///     .layer(opentelemetry_tracing_layer())
+///     .layer(opentelemetry_metrics_layer());
+/// # let _: Router = app;
+/// ```
+///
+/// [`opentelemetry_tracing_layer`]: super::opentelemetry_tracing_layer
+pub fn opentelemetry_metrics_layer() -> OtelMetricsLayer {
+    OtelMetricsLayer::new(&opentelemetry::global::meter(env!("CARGO_PKG_NAME")))
+}
+
+/// Layer recording the metrics of the HTTP server, see [`opentelemetry_metrics_layer`].
+#[derive(Clone)]
+pub struct OtelMetricsLayer {
+    metrics: Arc<Metrics>,
+}
+
+impl OtelMetricsLayer {
+    /// Create the instruments from `meter`.
+    pub fn new(meter: &Meter) -> Self {
+        let metrics = Metrics {
+            duration: meter
+                .f64_histogram("http.server.duration")
+                .with_description("Measures the duration of inbound HTTP requests.")
+                .with_unit(Unit::new("ms"))
+                .init(),
+            active_requests: meter
+                .i64_up_down_counter("http.server.active_requests")
+                .with_description(
+                    "Measures the number of concurrent HTTP requests that are currently in-flight.",
+                )
+                .with_unit(Unit::new("{request}"))
+                .init(),
+            request_size: meter
+                .u64_histogram("http.server.request.size")
+                .with_description("Measures the size of HTTP request messages (compressed).")
+                .with_unit(Unit::new("By"))
+                .init(),
+            response_size: meter
+                .u64_histogram("http.server.response.size")
+                .with_description("Measures the size of HTTP response messages (compressed).")
+                .with_unit(Unit::new("By"))
+                .init(),
+        };
+        OtelMetricsLayer {
+            metrics: Arc::new(metrics),
+        }
+    }
+}
+
+impl std::fmt::Debug for OtelMetricsLayer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "OtelMetricsLayer")
+    }
+}
+
+impl<S> Layer<S> for OtelMetricsLayer {
+    type Service = OtelMetricsMiddleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        OtelMetricsMiddleware {
+            inner,
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+struct Metrics {
+    duration: Histogram<f64>,
+    active_requests: UpDownCounter<i64>,
+    request_size: Histogram<u64>,
+    response_size: Histogram<u64>,
+}
+
+/// Middleware recording the metrics of the HTTP server, created by [`OtelMetricsLayer`].
+///
+/// See [`opentelemetry_metrics_layer`] for the recorded metrics and attributes.
+#[derive(Clone)]
+pub struct OtelMetricsMiddleware<S> {
+    inner: S,
+    metrics: Arc<Metrics>,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for OtelMetricsMiddleware<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Send + 'static,
+    S::Future: Send + 'static,
+    ReqBody: HttpBody,
+    ResBody: HttpBody,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let start = Instant::now();
+        let scheme = req
+            .uri()
+            .scheme()
+            .map_or_else(|| "HTTP".into(), http_scheme);
+        let mut attributes = vec![
+            KeyValue::new("http.method", http_request_method(req.method()).0),
+            KeyValue::new("http.scheme", scheme),
+            KeyValue::new("http.flavor", http_flavor(req.version())),
+        ];
+        // decremented on drop, even if the request is cancelled
+        let active_request = ActiveRequest::new(self.metrics.clone(), attributes.clone());
+        if let Some(route) = req.extensions().get::<MatchedPath>() {
+            attributes.push(KeyValue::new("http.route", route.as_str().to_owned()));
+        }
+        if let Some(size) = req.body().size_hint().exact() {
+            self.metrics
+                .request_size
+                .record(&OtelContext::current(), size, &attributes);
+        }
+        let future = self.inner.call(req);
+
+        Box::pin(async move {
+            let result = future.await;
+            let metrics = &active_request.metrics;
+            let cx = OtelContext::current();
+            if let Ok(response) = &result {
+                attributes.push(KeyValue::new(
+                    "http.status_code",
+                    i64::from(response.status().as_u16()),
+                ));
+                if let Some(size) = response.body().size_hint().exact() {
+                    metrics.response_size.record(&cx, size, &attributes);
+                }
+            }
+            let duration = start.elapsed().as_secs_f64() * 1000.0;
+            metrics.duration.record(&cx, duration, &attributes);
+            result
+        })
+    }
+}
+
+struct ActiveRequest {
+    metrics: Arc<Metrics>,
+    attributes: Vec<KeyValue>,
+}
+
+impl ActiveRequest {
+    fn new(metrics: Arc<Metrics>, attributes: Vec<KeyValue>) -> Self {
+        metrics
+            .active_requests
+            .add(&OtelContext::current(), 1, &attributes);
+        ActiveRequest {
+            metrics,
+            attributes,
+        }
+    }
+}
+
+impl Drop for ActiveRequest {
+    fn drop(&mut self) {
+        self.metrics
+            .active_requests
+            .add(&OtelContext::current(), -1, &self.attributes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, routing::get, Router};
+    use http::StatusCode;
+    use opentelemetry::sdk::{
+        export::metrics::{
+            aggregation::{Count, Sum},
+            InstrumentationLibraryReader,
+        },
+        metrics::{
+            aggregators::{HistogramAggregator, SumAggregator},
+            controllers, processors,
+            sdk_api::NumberKind,
+            selectors,
+        },
+    };
+    use opentelemetry::{metrics::MeterProvider as _, sdk::export::metrics::aggregation};
+    use tower::{Service, ServiceExt};
+
+    #[tokio::test]
+    async fn record_metrics_for_request() {
+        let controller = controllers::basic(processors::factory(
+            selectors::simple::histogram([1.0, 10.0, 100.0]),
+            aggregation::cumulative_temporality_selector(),
+        ))
+        .build();
+        let mut app = Router::new()
+            .route(
+                "/users/:id",
+                get(|| async { (StatusCode::CREATED, "created") }),
+            )
+            .layer(OtelMetricsLayer::new(&controller.meter("test")));
+        for (method, uri) in [
+            ("GET", "/users/123"),
+            ("GET", "/users/456"),
+            ("GET", "/idontexist"),
+            ("PURGE", "/users/789"),
+        ] {
+            let req = Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Body::from("hello"))
+                .unwrap();
+            app.ready().await.unwrap().call(req).await.unwrap();
+        }
+
+        let cx = OtelContext::current();
+        controller.collect(&cx).unwrap();
+        let mut records = vec![];
+        controller
+            .try_for_each(&mut |_library, reader| {
+                reader.try_for_each(
+                    &aggregation::cumulative_temporality_selector(),
+                    &mut |record| {
+                        let name = record.descriptor().name().to_owned();
+                        let mut attributes = record
+                            .attributes()
+                            .iter()
+                            .map(|(k, v)| format!("{k}={v}"))
+                            .collect::<Vec<_>>();
+                        attributes.sort();
+                        let aggregator = record.aggregator().unwrap().as_any();
+                        let value = if let Some(histogram) =
+                            aggregator.downcast_ref::<HistogramAggregator>()
+                        {
+                            format!("count={}", histogram.count()?)
+                        } else if let Some(sum) = aggregator.downcast_ref::<SumAggregator>() {
+                            format!("sum={}", sum.sum()?.to_i64(&NumberKind::I64))
+                        } else {
+                            "?".to_owned()
+                        };
+                        records.push(format!("{name} {} {value}", attributes.join(",")));
+                        Ok(())
+                    },
+                )
+            })
+            .unwrap();
+        records.sort();
+        insta::assert_yaml_snapshot!(records);
+    }
+}
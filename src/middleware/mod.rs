@@ -1,6 +1,22 @@
+mod captured_headers;
+mod client_ip;
+mod dyn_span;
+mod exclusions;
+#[cfg(feature = "metrics")]
+mod metrics;
 mod response_injector;
 mod trace_extractor;
 
+pub use client_ip::ClientIpStrategy;
+#[cfg(feature = "metrics")]
+pub use metrics::{opentelemetry_metrics_layer, OtelMetricsLayer, OtelMetricsMiddleware};
 pub use response_injector::response_with_trace_layer;
 pub use trace_extractor::opentelemetry_tracing_layer;
 pub use trace_extractor::opentelemetry_tracing_layer_grpc;
+#[cfg(feature = "tracing_subscriber_ext")]
+pub(crate) use trace_extractor::{default_tracer, set_default_tracer};
+pub use trace_extractor::{
+    FailureClassification, OtelLayerBuilder, OtelMakeGrpcSpan, OtelMakeSpan, OtelOnBodyChunk,
+    OtelOnEos, OtelOnFailure, OtelOnGrpcFailure, OtelOnRequest, OtelOnResponse, SemConvMode,
+    SpanNameStrategy,
+};
@@ -3,13 +3,30 @@
 //!
 //! See [`opentelemetry_tracing_layer`] for more details.
 
+use super::{
+    captured_headers::CapturedHeaders,
+    client_ip::{ClientAddress, ClientIpStrategy},
+    dyn_span::DynSpan,
+    exclusions::Exclusions,
+};
 use axum::{
-    extract::{ConnectInfo, MatchedPath, OriginalUri},
+    extract::{MatchedPath, OriginalUri},
     response::Response,
 };
-use http::{header, uri::Scheme, HeaderMap, Method, Request, Version};
-use opentelemetry::trace::{TraceContextExt, TraceId};
-use std::{borrow::Cow, net::SocketAddr, time::Duration};
+use http::{header, uri::Scheme, HeaderMap, HeaderName, Method, Request, Uri, Version};
+use opentelemetry::{
+    sdk::trace::Tracer,
+    trace::{
+        OrderMap, SamplingDecision, SpanContext, SpanId, SpanKind, TraceContextExt, TraceFlags,
+        TraceId,
+    },
+    Key,
+};
+use std::{
+    borrow::Cow,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 use tower_http::{
     classify::{
         GrpcErrorsAsFailures, GrpcFailureClass, ServerErrorsAsFailures, ServerErrorsFailureClass,
@@ -17,7 +34,8 @@ use tower_http::{
     },
     trace::{MakeSpan, OnBodyChunk, OnEos, OnFailure, OnRequest, OnResponse, TraceLayer},
 };
-use tracing::{field::Empty, Span};
+use tracing::{Span, Value};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 
 /// OpenTelemetry tracing middleware.
 ///
@@ -28,8 +46,8 @@ use tracing::{field::Empty, Span};
 ///
 /// The following fields will be set on the span:
 ///
-/// - `http.client_ip`: The client's IP address. Requires using
-/// [`Router::into_make_service_with_connect_info`]
+/// - `http.client_ip`: The client's IP address (the peer address includes the port), see [`ClientIpStrategy`].
+///   Requires using [`Router::into_make_service_with_connect_info`]
 /// - `http.flavor`: The protocol version used (http 1.1, http 2.0, etc)
 /// - `http.host`: The value of the `Host` header
 /// - `http.method`: The request method
@@ -40,7 +58,14 @@ use tracing::{field::Empty, Span};
 /// - `http.user_agent`: The value of the `User-Agent` header
 /// - `otel.kind`: Always `server`
 /// - `otel.status_code`: `OK` if the response is success, `ERROR` if it is a 5xx
-/// - `trace_id`: The trace id as tracted via the remote span context.
+/// - `trace_id`: The trace id as extracted via the remote span context, else the id of the new
+///   trace (generated by the tracer provider of the tracer, see [`OtelLayerBuilder::with_tracer`])
+/// - `http.request.header.<name>`, `http.response.header.<name>`: The value of the headers
+///   configured via [`OtelLayerBuilder::with_request_headers`], [`OtelLayerBuilder::with_response_headers`]
+///
+/// The `http.*` fields above follow the legacy conventions, the stable conventions (`http.request.method`,
+/// `url.path`, `client.address`,...) can be enabled with the environment variable
+/// `OTEL_SEMCONV_STABILITY_OPT_IN` or [`OtelLayerBuilder::with_semconv_mode`], see [`SemConvMode`].
 ///
 /// # Example
 ///
@@ -64,6 +89,8 @@ use tracing::{field::Empty, Span};
 /// # };
 /// ```
 ///
+/// To customize the layer (span name, client ip, failure,...), use [`OtelLayerBuilder`].
+///
 /// # Complete example
 ///
 /// See the "opentelemetry-jaeger" example for a complete setup that includes an OpenTelemetry
@@ -80,16 +107,21 @@ pub fn opentelemetry_tracing_layer() -> TraceLayer<
     OtelOnEos,
     OtelOnFailure,
 > {
-    TraceLayer::new_for_http()
-        .make_span_with(OtelMakeSpan)
-        .on_request(OtelOnRequest)
-        .on_response(OtelOnResponse)
-        .on_body_chunk(OtelOnBodyChunk)
-        .on_eos(OtelOnEos)
-        .on_failure(OtelOnFailure)
+    OtelLayerBuilder::default().build()
 }
 
 /// OpenTelemetry tracing middleware for gRPC.
+///
+/// In addition to the HTTP fields (see [`opentelemetry_tracing_layer`]), the span follows the
+/// [RPC semantic conventions][rpc]:
+///
+/// - `otel.name`: `$package.$service/$method` (the path of the request, without the leading `/`)
+/// - `rpc.system`: Always `grpc`
+/// - `rpc.service`: `$package.$service`, parsed from the path of the request
+/// - `rpc.method`: `$method`, parsed from the path of the request
+/// - `rpc.grpc.status_code`: The numeric status code of the gRPC request
+///
+/// [rpc]: https://opentelemetry.io/docs/specs/semconv/rpc/grpc/
 pub fn opentelemetry_tracing_layer_grpc() -> TraceLayer<
     SharedClassifier<GrpcErrorsAsFailures>,
     OtelMakeGrpcSpan,
@@ -99,179 +131,672 @@ pub fn opentelemetry_tracing_layer_grpc() -> TraceLayer<
     OtelOnEos,
     OtelOnGrpcFailure,
 > {
-    TraceLayer::new_for_grpc()
-        .make_span_with(OtelMakeGrpcSpan)
-        .on_request(OtelOnRequest)
-        .on_response(OtelOnResponse)
-        .on_body_chunk(OtelOnBodyChunk)
-        .on_eos(OtelOnEos)
-        .on_failure(OtelOnGrpcFailure)
+    OtelLayerBuilder::default().build_grpc()
 }
 
-/// A [`MakeSpan`] that creates tracing spans using [OpenTelemetry's conventional field names][otel].
+/// Builder to configure the layers returned by [`opentelemetry_tracing_layer`]
+/// and [`opentelemetry_tracing_layer_grpc`].
 ///
-/// [otel]: https://github.com/open-telemetry/opentelemetry-specification/blob/main/specification/trace/semantic_conventions/http.md
-#[derive(Clone, Copy, Debug)]
-pub struct OtelMakeSpan;
+/// ```rust
+/// use axum::{routing::get, Router};
+/// use axum_tracing_opentelemetry::{ClientIpStrategy, OtelLayerBuilder, SpanNameStrategy};
+///
+/// let app: Router = Router::new()
+///     .route("/", get(|| async {}))
+///     .route("/health", get(|| async {}))
+///     .layer(
+///         OtelLayerBuilder::default()
+///             .with_span_name(SpanNameStrategy::Route)
+///             .with_client_ip(ClientIpStrategy::ConnectInfo)
+///             .with_excluded_path("/health")
+///             .build(),
+///     );
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct OtelLayerBuilder {
+    config: LayerConfig,
+}
 
-impl<B> MakeSpan<B> for OtelMakeSpan {
-    fn make_span(&mut self, req: &Request<B>) -> Span {
-        let user_agent = req
-            .headers()
-            .get(header::USER_AGENT)
-            .map_or("", |h| h.to_str().unwrap_or(""));
+impl OtelLayerBuilder {
+    /// Define how the name of the span (`otel.name`) is computed.
+    /// Only used by the HTTP layer, the gRPC layer follows the gRPC conventions.
+    pub fn with_span_name(mut self, span_name: SpanNameStrategy) -> Self {
+        self.config.span_name = span_name;
+        self
+    }
 
-        let host = req
-            .headers()
-            .get(header::HOST)
-            .map_or("", |h| h.to_str().unwrap_or(""));
+    /// Define how the client ip (`http.client_ip`, `client.address`) is extracted from the request,
+    /// default to [`ClientIpStrategy::XForwardedForOrConnectInfo`].
+    pub fn with_client_ip(mut self, client_ip: ClientIpStrategy) -> Self {
+        self.config.client_ip = client_ip;
+        self
+    }
 
-        let scheme = req
-            .uri()
-            .scheme()
-            .map_or_else(|| "HTTP".into(), http_scheme);
+    /// Define which responses mark the span as `otel.status_code = ERROR`.
+    /// Only used by the HTTP layer, the gRPC layer relies on the `grpc-status`.
+    pub fn with_failure_classification(
+        mut self,
+        failure_classification: FailureClassification,
+    ) -> Self {
+        self.config.failure_classification = failure_classification;
+        self
+    }
 
-        let http_route = req
-            .extensions()
-            .get::<MatchedPath>()
-            .map_or_else(|| "", |mp| mp.as_str())
-            .to_owned();
+    /// Requests with this path (query excluded) are not traced.
+    /// The path should match exactly, or start with the path if it ends with `*` (eg `"/internal/*"`).
+    /// Can be called several times to exclude several paths.
+    ///
+    /// Paths (and routes) to exclude are also read from the environment variable
+    /// `OTEL_RUST_EXCLUDED_URLS` (comma-separated list).
+    pub fn with_excluded_path<S: Into<String>>(mut self, path: S) -> Self {
+        self.config.exclusions.add_path(path.into());
+        self
+    }
 
-        let uri = if let Some(uri) = req.extensions().get::<OriginalUri>() {
-            uri.0.clone()
-        } else {
-            req.uri().clone()
-        };
-        let http_target = uri
-            .path_and_query()
-            .map(|path_and_query| path_and_query.to_string())
-            .unwrap_or_else(|| uri.path().to_owned());
-
-        let client_ip = parse_x_forwarded_for(req.headers())
-            .or_else(|| {
-                req.extensions()
-                    .get::<ConnectInfo<SocketAddr>>()
-                    .map(|ConnectInfo(client_ip)| Cow::from(client_ip.to_string()))
-            })
-            .unwrap_or_default();
-        let http_method_v = http_method(req.method());
-        let name = format!("{http_method_v} {http_route}").trim().to_string();
-        let (trace_id, otel_context) =
-            create_context_with_trace(extract_remote_context(req.headers()));
-        let span = tracing::info_span!(
+    /// Requests handled by this route (as the [`MatchedPath`], eg `"/users/:id"`) are not traced.
+    /// Can be called several times to exclude several routes.
+    pub fn with_excluded_route<S: Into<String>>(mut self, route: S) -> Self {
+        self.config.exclusions.add_route(route.into());
+        self
+    }
+
+    /// Requests for which the predicate returns `true` are not traced.
+    /// Can be called several times, a request is excluded if any predicate returns `true`.
+    ///
+    /// ```rust
+    /// use axum_tracing_opentelemetry::OtelLayerBuilder;
+    ///
+    /// let layer = OtelLayerBuilder::default()
+    ///     .with_exclusion(|_method, _uri, headers| headers.contains_key("x-probe"))
+    ///     .build();
+    /// ```
+    pub fn with_exclusion<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(&Method, &Uri, &HeaderMap) -> bool + Send + Sync + 'static,
+    {
+        self.config.exclusions.add_predicate(predicate);
+        self
+    }
+
+    /// Define the tracer (the one of the `tracing_opentelemetry` layer) whose tracer provider
+    /// generates the trace id (`IdGenerator`) and decides the sampling (`Sampler`) of the requests
+    /// without remote parent, so `trace_id` is set at the creation of the span.
+    ///
+    /// Default is the tracer installed by `init_subscribers` (or `OtelSetup`), without tracer the
+    /// trace id is recorded just after the creation of the span.
+    pub fn with_tracer(mut self, tracer: Tracer) -> Self {
+        self.config.tracer = Some(tracer);
+        self
+    }
+
+    /// Define which semantic conventions (legacy and/or stable) are used for the attributes of the span.
+    /// Default is read from the environment variable `OTEL_SEMCONV_STABILITY_OPT_IN`, see [`SemConvMode`].
+    pub fn with_semconv_mode(mut self, semconv_mode: SemConvMode) -> Self {
+        self.config.semconv_mode = Some(semconv_mode);
+        self
+    }
+
+    /// Record the value of these request headers as span attributes `http.request.header.<name>`.
+    /// Headers are also read from the environment variable `OTEL_INSTRUMENTATION_HTTP_SERVER_CAPTURE_REQUEST_HEADERS`
+    /// (comma-separated list of header names).
+    ///
+    /// The values of a header present several times are joined with `,`, so the boundaries of
+    /// values containing a `,` (eg `Set-Cookie`) are lost.
+    /// The values of sensitive headers are redacted, see [`OtelLayerBuilder::with_redacted_headers`].
+    pub fn with_request_headers<I>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = HeaderName>,
+    {
+        self.config.captured_headers.add_request_headers(names);
+        self
+    }
+
+    /// Record the value of these response headers as span attributes `http.response.header.<name>`.
+    /// Headers are also read from the environment variable `OTEL_INSTRUMENTATION_HTTP_SERVER_CAPTURE_RESPONSE_HEADERS`
+    /// (comma-separated list of header names).
+    ///
+    /// The values of a header present several times are joined with `,`, so the boundaries of
+    /// values containing a `,` (eg `Set-Cookie`) are lost.
+    /// The values of sensitive headers are redacted, see [`OtelLayerBuilder::with_redacted_headers`].
+    pub fn with_response_headers<I>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = HeaderName>,
+    {
+        self.config.captured_headers.add_response_headers(names);
+        self
+    }
+
+    /// Replace the list of headers whose value is recorded as `[REDACTED]`
+    /// when captured (default: `authorization`, `proxy-authorization`, `cookie`, `set-cookie`).
+    pub fn with_redacted_headers<I>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = HeaderName>,
+    {
+        self.config.captured_headers.set_redacted_headers(names);
+        self
+    }
+
+    /// Build the layer for HTTP services (same as [`opentelemetry_tracing_layer`] when not customized).
+    pub fn build(
+        self,
+    ) -> TraceLayer<
+        SharedClassifier<ServerErrorsAsFailures>,
+        OtelMakeSpan,
+        OtelOnRequest,
+        OtelOnResponse,
+        OtelOnBodyChunk,
+        OtelOnEos,
+        OtelOnFailure,
+    > {
+        let config = Arc::new(self.config.with_env());
+        let span = DynSpan::new(
             "HTTP request",
-            otel.name= %name,
-            http.client_ip = %client_ip,
-            http.flavor = %http_flavor(req.version()),
-            http.host = %host,
-            http.method = %http_method_v,
-            http.route = %http_route,
-            http.scheme = %scheme,
-            http.status_code = Empty,
-            http.target = %http_target,
-            http.user_agent = %user_agent,
-            otel.kind = %"server", //opentelemetry::trace::SpanKind::Server
-            otel.status_code = Empty,
-            trace_id = %trace_id,
+            module_path!(),
+            &config.span_fields(&[]),
+            &config.captured_headers.span_fields(),
         );
-        match otel_context {
-            OtelContext::Remote(cx) => {
-                tracing_opentelemetry::OpenTelemetrySpanExt::set_parent(&span, cx)
-            }
-            OtelContext::Local(cx) => {
-                tracing_opentelemetry::OpenTelemetrySpanExt::add_link(&span, cx)
-            }
+        TraceLayer::new_for_http()
+            .make_span_with(OtelMakeSpan {
+                config: config.clone(),
+                span,
+            })
+            .on_request(OtelOnRequest)
+            .on_response(OtelOnResponse { config })
+            .on_body_chunk(OtelOnBodyChunk)
+            .on_eos(OtelOnEos)
+            .on_failure(OtelOnFailure)
+    }
+
+    /// Build the layer for gRPC services (same as [`opentelemetry_tracing_layer_grpc`] when not customized).
+    pub fn build_grpc(
+        self,
+    ) -> TraceLayer<
+        SharedClassifier<GrpcErrorsAsFailures>,
+        OtelMakeGrpcSpan,
+        OtelOnRequest,
+        OtelOnResponse,
+        OtelOnBodyChunk,
+        OtelOnEos,
+        OtelOnGrpcFailure,
+    > {
+        let config = Arc::new(self.config.with_env());
+        let span = DynSpan::new(
+            "grpc request",
+            module_path!(),
+            &config.span_fields(GRPC_SPAN_FIELDS),
+            &config.captured_headers.span_fields(),
+        );
+        TraceLayer::new_for_grpc()
+            .make_span_with(OtelMakeGrpcSpan {
+                config: config.clone(),
+                span,
+            })
+            .on_request(OtelOnRequest)
+            .on_response(OtelOnResponse { config })
+            .on_body_chunk(OtelOnBodyChunk)
+            .on_eos(OtelOnEos)
+            .on_failure(OtelOnGrpcFailure)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct LayerConfig {
+    span_name: SpanNameStrategy,
+    client_ip: ClientIpStrategy,
+    failure_classification: FailureClassification,
+    semconv_mode: Option<SemConvMode>,
+    exclusions: Exclusions,
+    captured_headers: CapturedHeaders,
+    tracer: Option<Tracer>,
+}
+
+impl LayerConfig {
+    fn with_env(mut self) -> Self {
+        if self.semconv_mode.is_none() {
+            self.semconv_mode = Some(SemConvMode::from_env());
         }
-        span
+        self.exclusions.add_from_env();
+        self.captured_headers.add_headers_from_env();
+        self
+    }
+
+    /// The fields of the span, except the captured headers.
+    fn span_fields(&self, extra_fields: &[&'static str]) -> Vec<&'static str> {
+        let semconv_fields: &[&[&str]] = match self.semconv_mode.unwrap_or_default() {
+            SemConvMode::Legacy => &[LEGACY_HTTP_SPAN_FIELDS],
+            SemConvMode::Stable => &[STABLE_HTTP_SPAN_FIELDS],
+            SemConvMode::Duplicate => &[LEGACY_HTTP_SPAN_FIELDS, STABLE_HTTP_SPAN_FIELDS],
+        };
+        SPAN_FIELDS
+            .iter()
+            .chain(semconv_fields.iter().flat_map(|fields| fields.iter()))
+            .chain(extra_fields.iter())
+            .copied()
+            .collect()
+    }
+
+    fn is_excluded<B>(&self, req: &Request<B>, uri: &Uri) -> bool {
+        let route = req.extensions().get::<MatchedPath>().map(|mp| mp.as_str());
+        self.exclusions
+            .is_excluded(req.method(), uri, req.headers(), route)
     }
 }
 
-/// A [`MakeSpan`] that creates tracing spans using [OpenTelemetry's conventional field names][otel] for gRPC services.
+/// The [HTTP semantic conventions] used for the attributes of the span.
 ///
-/// [otel]: https://github.com/open-telemetry/opentelemetry-specification/blob/main/specification/trace/semantic_conventions/http.md
-#[derive(Clone, Copy, Debug)]
-pub struct OtelMakeGrpcSpan;
+/// [HTTP semantic conventions]: https://opentelemetry.io/docs/specs/semconv/http/http-spans/
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SemConvMode {
+    /// Attributes before the stabilization of the conventions (v1.20):
+    /// `http.method`, `http.target`, `http.flavor`, `http.client_ip`, `http.status_code`,...
+    #[default]
+    Legacy,
+    /// Stable attributes: `http.request.method`, `url.path`, `url.query`, `url.scheme`,
+    /// `server.address`, `client.address` (without the port of the peer, recorded in `client.port`),
+    /// `network.protocol.version`, `http.response.status_code`,...
+    Stable,
+    /// Both the legacy and the stable attributes.
+    Duplicate,
+}
 
-impl<B> MakeSpan<B> for OtelMakeGrpcSpan {
-    fn make_span(&mut self, req: &Request<B>) -> Span {
-        let user_agent = req
-            .headers()
-            .get(header::USER_AGENT)
-            .map_or("", |h| h.to_str().unwrap_or(""));
+impl SemConvMode {
+    /// Read the mode from the environment variable `OTEL_SEMCONV_STABILITY_OPT_IN`
+    /// (comma-separated list): `http/dup` for [`SemConvMode::Duplicate`], `http` for [`SemConvMode::Stable`],
+    /// else [`SemConvMode::Legacy`].
+    pub fn from_env() -> Self {
+        let value = std::env::var("OTEL_SEMCONV_STABILITY_OPT_IN").unwrap_or_default();
+        let mode = Self::from_opt_in(&value);
+        tracing::debug!(target: "otel::setup", OTEL_SEMCONV_STABILITY_OPT_IN = ?mode);
+        mode
+    }
 
-        let host = req
-            .headers()
-            .get(header::HOST)
-            .map_or("", |h| h.to_str().unwrap_or(""));
+    fn from_opt_in(value: &str) -> Self {
+        let values = value.split(',').map(str::trim).collect::<Vec<_>>();
+        if values.contains(&"http/dup") {
+            SemConvMode::Duplicate
+        } else if values.contains(&"http") {
+            SemConvMode::Stable
+        } else {
+            SemConvMode::Legacy
+        }
+    }
+}
 
-        let scheme = req
-            .uri()
-            .scheme()
-            .map_or_else(|| "HTTP".into(), http_scheme);
+/// How to compute the name of the span (`otel.name`) of an HTTP request.
+#[derive(Clone, Default)]
+pub enum SpanNameStrategy {
+    /// `"{method} {route}"` (eg `"GET /users/:id"`), only the method if no route matched.
+    #[default]
+    MethodAndRoute,
+    /// The matched route, the method if no route matched.
+    Route,
+    /// The same name for every span.
+    Fixed(Cow<'static, str>),
+    /// Computed from the method and the matched route (`""` if no route matched).
+    Custom(SpanNameFn),
+}
+
+type SpanNameFn = Arc<dyn Fn(&Method, &str) -> String + Send + Sync>;
+
+impl SpanNameStrategy {
+    fn span_name(&self, method: &Method, http_route: &str) -> String {
+        match self {
+            SpanNameStrategy::MethodAndRoute => format!("{} {http_route}", http_method(method))
+                .trim()
+                .to_string(),
+            SpanNameStrategy::Route if http_route.is_empty() => http_method(method).into_owned(),
+            SpanNameStrategy::Route => http_route.to_string(),
+            SpanNameStrategy::Fixed(name) => name.to_string(),
+            SpanNameStrategy::Custom(f) => f(method, http_route),
+        }
+    }
+}
+
+impl std::fmt::Debug for SpanNameStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MethodAndRoute => write!(f, "MethodAndRoute"),
+            Self::Route => write!(f, "Route"),
+            Self::Fixed(name) => f.debug_tuple("Fixed").field(name).finish(),
+            Self::Custom(_) => write!(f, "Custom(..)"),
+        }
+    }
+}
+
+/// Which HTTP responses are reported as failure (`otel.status_code = ERROR`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FailureClassification {
+    /// Only 5xx responses (and errors of the inner service).
+    #[default]
+    ServerErrors,
+    /// 4xx and 5xx responses (and errors of the inner service).
+    ClientAndServerErrors,
+}
+
+/// A [`MakeSpan`] that creates tracing spans using [OpenTelemetry's conventional field names][otel].
+///
+/// [otel]: https://github.com/open-telemetry/opentelemetry-specification/blob/main/specification/trace/semantic_conventions/http.md
+#[derive(Clone, Debug)]
+pub struct OtelMakeSpan {
+    config: Arc<LayerConfig>,
+    span: DynSpan,
+}
+
+impl<B> MakeSpan<B> for OtelMakeSpan {
+    fn make_span(&mut self, req: &Request<B>) -> Span {
+        let uri = req
+            .extensions()
+            .get::<OriginalUri>()
+            .map_or(req.uri(), |uri| &uri.0);
+        if self.config.is_excluded(req, uri) {
+            return Span::none();
+        }
 
         let http_route = req
             .extensions()
             .get::<MatchedPath>()
-            .map_or("", |mp| mp.as_str())
-            .to_owned();
+            .map_or("", |mp| mp.as_str());
+        let name = self.config.span_name.span_name(req.method(), http_route);
+        new_span(&self.span, &self.config, req, uri, &name, &[])
+    }
+}
 
-        let uri = if let Some(uri) = req.extensions().get::<OriginalUri>() {
-            uri.0.clone()
-        } else {
-            req.uri().clone()
-        };
-        let http_target = uri
-            .path_and_query()
-            .map(|path_and_query| path_and_query.to_string())
-            .unwrap_or_else(|| uri.path().to_owned());
-
-        let client_ip = parse_x_forwarded_for(req.headers())
-            .or_else(|| {
-                req.extensions()
-                    .get::<ConnectInfo<SocketAddr>>()
-                    .map(|ConnectInfo(client_ip)| Cow::from(client_ip.to_string()))
-            })
-            .unwrap_or_default();
-        let http_method_v = http_method(req.method());
-        let (trace_id, otel_context) =
-            create_context_with_trace(extract_remote_context(req.headers()));
-        let span = tracing::info_span!(
-            "grpc request",
-            otel.name = %http_target, // Convetion in gRPC tracing.
-            http.client_ip = %client_ip,
-            http.flavor = %http_flavor(req.version()),
-            http.grpc_status = Empty,
-            http.host = %host,
-            http.method = %http_method_v,
-            http.route = %http_route,
-            http.scheme = %scheme,
-            http.status_code = Empty,
-            http.target = %http_target,
-            http.user_agent = %user_agent,
-            otel.kind = %"server", //opentelemetry::trace::SpanKind::Server
-            otel.status_code = Empty,
-            trace_id = %trace_id,
-        );
-        match otel_context {
-            OtelContext::Remote(cx) => {
-                tracing_opentelemetry::OpenTelemetrySpanExt::set_parent(&span, cx)
+/// A [`MakeSpan`] that creates tracing spans using [OpenTelemetry's conventional field names][otel] for gRPC services.
+///
+/// [otel]: https://github.com/open-telemetry/opentelemetry-specification/blob/main/specification/trace/semantic_conventions/http.md
+#[derive(Clone, Debug)]
+pub struct OtelMakeGrpcSpan {
+    config: Arc<LayerConfig>,
+    span: DynSpan,
+}
+
+impl<B> MakeSpan<B> for OtelMakeGrpcSpan {
+    fn make_span(&mut self, req: &Request<B>) -> Span {
+        let uri = req
+            .extensions()
+            .get::<OriginalUri>()
+            .map_or(req.uri(), |uri| &uri.0);
+        if self.config.is_excluded(req, uri) {
+            return Span::none();
+        }
+
+        match parse_grpc_path(uri.path()) {
+            Some((service, method)) => {
+                // Convention in gRPC tracing: `$package.$service/$method`
+                let name = format!("{service}/{method}");
+                new_span(
+                    &self.span,
+                    &self.config,
+                    req,
+                    uri,
+                    &name,
+                    &[
+                        ("rpc.system", &"grpc"),
+                        ("rpc.service", &service),
+                        ("rpc.method", &method),
+                    ],
+                )
             }
-            OtelContext::Local(cx) => {
-                tracing_opentelemetry::OpenTelemetrySpanExt::add_link(&span, cx)
+            None => {
+                let http_target = uri
+                    .path_and_query()
+                    .map_or_else(|| uri.path(), |path_and_query| path_and_query.as_str());
+                new_span(
+                    &self.span,
+                    &self.config,
+                    req,
+                    uri,
+                    http_target,
+                    &[("rpc.system", &"grpc")],
+                )
             }
         }
-        span
     }
 }
 
-fn parse_x_forwarded_for(headers: &HeaderMap) -> Option<Cow<'_, str>> {
-    let value = headers.get("x-forwarded-for")?;
-    let value = value.to_str().ok()?;
-    let mut ips = value.split(',');
-    Some(ips.next()?.trim().into())
+/// Split the path of a gRPC request `/$package.$service/$method` into `($package.$service, $method)`.
+fn parse_grpc_path(path: &str) -> Option<(&str, &str)> {
+    let (service, method) = path.strip_prefix('/')?.split_once('/')?;
+    (!service.is_empty() && !method.is_empty() && !method.contains('/'))
+        .then_some((service, method))
+}
+
+const SPAN_FIELDS: &[&str] = &[
+    "otel.name",
+    "otel.kind",
+    "otel.status_code",
+    "trace_id",
+    "http.route",
+];
+
+const LEGACY_HTTP_SPAN_FIELDS: &[&str] = &[
+    "http.client_ip",
+    "http.flavor",
+    "http.host",
+    "http.method",
+    "http.scheme",
+    "http.status_code",
+    "http.target",
+    "http.user_agent",
+];
+
+const STABLE_HTTP_SPAN_FIELDS: &[&str] = &[
+    "client.address",
+    "client.port",
+    "error.type",
+    "http.request.method",
+    "http.request.method_original",
+    "http.response.status_code",
+    "network.protocol.version",
+    "server.address",
+    "server.port",
+    "url.path",
+    "url.query",
+    "url.scheme",
+    "user_agent.original",
+];
+
+const GRPC_SPAN_FIELDS: &[&str] = &[
+    "http.grpc_status",
+    "rpc.system",
+    "rpc.service",
+    "rpc.method",
+    "rpc.grpc.status_code",
+];
+
+/// Create the span with the fields (of every [`SemConvMode`]) extracted from the request,
+/// only the fields declared by `dyn_span` (for the configured mode) are kept.
+fn new_span<B>(
+    dyn_span: &DynSpan,
+    config: &LayerConfig,
+    req: &Request<B>,
+    uri: &Uri,
+    name: &str,
+    extra_values: &[(&str, &dyn Value)],
+) -> Span {
+    let user_agent = req
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|h| h.to_str().ok());
+
+    let host = req
+        .headers()
+        .get(header::HOST)
+        .map_or("", |h| h.to_str().unwrap_or(""));
+
+    let scheme = req
+        .uri()
+        .scheme()
+        .map_or_else(|| "HTTP".into(), http_scheme);
+    let url_scheme = req
+        .uri()
+        .scheme()
+        .map_or_else(|| "http".into(), http_scheme);
+
+    let http_route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("", |mp| mp.as_str());
+
+    let http_target = uri
+        .path_and_query()
+        .map_or_else(|| uri.path(), |path_and_query| path_and_query.as_str());
+
+    let (server_address, server_port) = server_address_and_port(req.uri(), host);
+    let client = config.client_ip.client_ip(req);
+    let client_ip = client
+        .as_ref()
+        .map_or_else(String::new, ToString::to_string);
+    let client_ip = client_ip.as_str();
+    let client_address = client.as_ref().map(ClientAddress::address);
+    let client_address = client_address.as_deref();
+    let client_port = client.as_ref().and_then(ClientAddress::port);
+    let http_method_v = http_method(req.method());
+    let http_method_v = http_method_v.as_ref();
+    let (request_method, request_method_original) = http_request_method(req.method());
+    let http_flavor_v = http_flavor(req.version());
+    let http_flavor_v = http_flavor_v.as_ref();
+    let protocol_version = network_protocol_version(req.version());
+    let protocol_version = protocol_version.as_ref();
+    let scheme = scheme.as_ref();
+    let url_scheme = url_scheme.as_ref();
+    let (url_path, url_query) = (uri.path(), uri.query());
+    let remote_context = extract_remote_context(req.headers());
+    let parent_context = if remote_context.span().span_context().trace_id() != TraceId::INVALID {
+        Some(remote_context)
+    } else {
+        // the attributes of the request (declared by the span) available to the sampler
+        let attributes = [
+            ("http.host", Some(host)),
+            ("http.method", Some(http_method_v)),
+            ("http.route", Some(http_route)),
+            ("http.scheme", Some(scheme)),
+            ("http.target", Some(http_target)),
+            ("http.request.method", Some(request_method)),
+            ("server.address", server_address),
+            ("url.path", Some(url_path)),
+            ("url.scheme", Some(url_scheme)),
+        ]
+        .into_iter()
+        .filter(|(key, _)| dyn_span.has_field(key))
+        .filter_map(|(key, value)| Some((Key::from_static_str(key), value?.to_owned().into())))
+        .collect();
+        root_context(config.tracer.as_ref(), name, &attributes)
+    };
+    let trace_id = parent_context
+        .as_ref()
+        .map(|cx| cx.span().span_context().trace_id().to_string());
+    let trace_id = trace_id.as_deref();
+    let http_user_agent = user_agent.unwrap_or("");
+    let mut values: Vec<(&str, &dyn Value)> = vec![
+        ("otel.name", &name),
+        ("otel.kind", &"server"), //opentelemetry::trace::SpanKind::Server
+        ("trace_id", &trace_id),
+        ("http.route", &http_route),
+        // legacy
+        ("http.client_ip", &client_ip),
+        ("http.flavor", &http_flavor_v),
+        ("http.host", &host),
+        ("http.method", &http_method_v),
+        ("http.scheme", &scheme),
+        ("http.target", &http_target),
+        ("http.user_agent", &http_user_agent),
+        // stable
+        ("client.address", &client_address),
+        ("client.port", &client_port),
+        ("http.request.method", &request_method),
+        ("http.request.method_original", &request_method_original),
+        ("network.protocol.version", &protocol_version),
+        ("server.address", &server_address),
+        ("server.port", &server_port),
+        ("url.path", &url_path),
+        ("url.query", &url_query),
+        ("url.scheme", &url_scheme),
+        ("user_agent.original", &user_agent),
+    ];
+    values.extend_from_slice(extra_values);
+    let span = dyn_span.span(&values);
+    config
+        .captured_headers
+        .record_request_headers(req.headers(), &span);
+    match parent_context {
+        Some(cx) => span.set_parent(cx),
+        None => record_root_trace_id(&span),
+    }
+    span
+}
+
+/// The tracer installed by `init_subscribers` (or `OtelSetup`), used when the layer is built
+/// without [`OtelLayerBuilder::with_tracer`].
+static DEFAULT_TRACER: RwLock<Option<Tracer>> = RwLock::new(None);
+
+#[cfg(any(test, feature = "tracing_subscriber_ext"))]
+pub(crate) fn set_default_tracer(tracer: &Tracer) {
+    *DEFAULT_TRACER.write().unwrap_or_else(|e| e.into_inner()) = Some(tracer.clone());
+}
+
+pub(crate) fn default_tracer() -> Option<Tracer> {
+    DEFAULT_TRACER
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+}
+
+/// Without a remote parent (and outside of a span), the span is the root of a new trace: its
+/// trace id is generated and the sampling decided (with the `attributes` of the request) by the
+/// `IdGenerator` and the `Sampler` of the tracer provider, before the creation of the span (so
+/// `trace_id` is set at creation).
+/// The returned context is the parent of the span, its span id is invalid so the span is exported
+/// as a root span (no parent, no link).
+fn root_context(
+    tracer: Option<&Tracer>,
+    name: &str,
+    attributes: &OrderMap<Key, opentelemetry::Value>,
+) -> Option<opentelemetry::Context> {
+    if !Span::current().is_none() {
+        return None;
+    }
+    let tracer = match tracer {
+        Some(tracer) => tracer.clone(),
+        None => default_tracer()?,
+    };
+    let provider = tracer.provider()?;
+    let config = provider.config();
+    let trace_id = config.id_generator.new_trace_id();
+    let sampling = config.sampler.should_sample(
+        None,
+        trace_id,
+        name,
+        &SpanKind::Server,
+        attributes,
+        &[],
+        tracer.instrumentation_library(),
+    );
+    let trace_flags = match sampling.decision {
+        SamplingDecision::RecordAndSample => TraceFlags::SAMPLED,
+        _ => TraceFlags::default(),
+    };
+    let span_context = SpanContext::new(
+        trace_id,
+        SpanId::INVALID,
+        trace_flags,
+        false,
+        sampling.trace_state,
+    );
+    Some(opentelemetry::Context::new().with_remote_span_context(span_context))
+}
+
+/// Without the tracer provider (or inside a span), the trace id is generated (and the sampling
+/// decided) by the tracer of the `tracing_opentelemetry` layer, it is only available once the span
+/// is created, so `trace_id` is recorded just after.
+fn record_root_trace_id(span: &Span) {
+    let trace_id = span.context().span().span_context().trace_id();
+    if trace_id != TraceId::INVALID {
+        span.record("trace_id", tracing::field::display(trace_id));
+    }
 }
 
-fn http_method(method: &Method) -> Cow<'static, str> {
+pub(super) fn http_method(method: &Method) -> Cow<'static, str> {
     match method {
         &Method::CONNECT => "CONNECT".into(),
         &Method::DELETE => "DELETE".into(),
@@ -286,7 +811,24 @@ fn http_method(method: &Method) -> Cow<'static, str> {
     }
 }
 
-fn http_flavor(version: Version) -> Cow<'static, str> {
+/// The method for `http.request.method`, known methods are used as is, else `_OTHER`
+/// with the original method for `http.request.method_original`.
+pub(super) fn http_request_method(method: &Method) -> (&'static str, Option<&str>) {
+    match method {
+        &Method::CONNECT => ("CONNECT", None),
+        &Method::DELETE => ("DELETE", None),
+        &Method::GET => ("GET", None),
+        &Method::HEAD => ("HEAD", None),
+        &Method::OPTIONS => ("OPTIONS", None),
+        &Method::PATCH => ("PATCH", None),
+        &Method::POST => ("POST", None),
+        &Method::PUT => ("PUT", None),
+        &Method::TRACE => ("TRACE", None),
+        other => ("_OTHER", Some(other.as_str())),
+    }
+}
+
+pub(super) fn http_flavor(version: Version) -> Cow<'static, str> {
     match version {
         Version::HTTP_09 => "0.9".into(),
         Version::HTTP_10 => "1.0".into(),
@@ -297,7 +839,36 @@ fn http_flavor(version: Version) -> Cow<'static, str> {
     }
 }
 
-fn http_scheme(scheme: &Scheme) -> Cow<'static, str> {
+fn network_protocol_version(version: Version) -> Cow<'static, str> {
+    match version {
+        Version::HTTP_09 => "0.9".into(),
+        Version::HTTP_10 => "1.0".into(),
+        Version::HTTP_11 => "1.1".into(),
+        Version::HTTP_2 => "2".into(),
+        Version::HTTP_3 => "3".into(),
+        other => format!("{other:?}").into(),
+    }
+}
+
+/// `server.address` & `server.port` from the authority of the uri (eg HTTP/2), or the `Host` header.
+fn server_address_and_port<'a>(uri: &'a Uri, host: &'a str) -> (Option<&'a str>, Option<u16>) {
+    if let Some(authority) = uri.authority() {
+        return (Some(authority.host()), authority.port_u16());
+    }
+    if host.is_empty() {
+        return (None, None);
+    }
+    match host.parse::<http::uri::Authority>() {
+        Ok(authority) => {
+            // `authority.host()` borrows from the parsed authority, so slice `host` instead
+            let address = &host[..authority.host().len()];
+            (Some(address), authority.port_u16())
+        }
+        Err(_) => (Some(host), None),
+    }
+}
+
+pub(super) fn http_scheme(scheme: &Scheme) -> Cow<'static, str> {
     if scheme == &Scheme::HTTP {
         "http".into()
     } else if scheme == &Scheme::HTTPS {
@@ -324,39 +895,6 @@ fn extract_remote_context(headers: &http::HeaderMap) -> opentelemetry::Context {
     opentelemetry::global::get_text_map_propagator(|propagator| propagator.extract(&extractor))
 }
 
-enum OtelContext {
-    Remote(opentelemetry::Context),
-    Local(opentelemetry::trace::SpanContext),
-}
-
-//HACK create a context with a trace_id (if not set) before call to
-// `tracing_opentelemetry::OpenTelemetrySpanExt::set_parent`
-// else trace_id is defined too late and the `info_span` log `trace_id: ""`
-// Use the default global tracer (named "") to start the trace
-fn create_context_with_trace(remote_context: opentelemetry::Context) -> (TraceId, OtelContext) {
-    if !remote_context.span().span_context().is_valid() {
-        // create a fake remote context but with a fresh new trace_id
-        use opentelemetry::sdk::trace::IdGenerator;
-        use opentelemetry::sdk::trace::RandomIdGenerator;
-        use opentelemetry::trace::SpanContext;
-        let trace_id = RandomIdGenerator::default().new_trace_id();
-        let span_id = RandomIdGenerator::default().new_span_id();
-        let new_span_context = SpanContext::new(
-            trace_id,
-            span_id,
-            remote_context.span().span_context().trace_flags(),
-            false,
-            remote_context.span().span_context().trace_state().clone(),
-        );
-        (trace_id, OtelContext::Local(new_span_context))
-    } else {
-        let remote_span = remote_context.span();
-        let span_context = remote_span.span_context();
-        let trace_id = span_context.trace_id();
-        (trace_id, OtelContext::Remote(remote_context))
-    }
-}
-
 /// Callback that [`Trace`] will call when it receives a request.
 ///
 /// [`Trace`]: tower_http::trace::Trace
@@ -371,19 +909,43 @@ impl<B> OnRequest<B> for OtelOnRequest {
 /// Callback that [`Trace`] will call when it receives a response.
 ///
 /// [`Trace`]: tower_http::trace::Trace
-#[derive(Clone, Copy, Debug)]
-pub struct OtelOnResponse;
+#[derive(Clone, Debug, Default)]
+pub struct OtelOnResponse {
+    config: Arc<LayerConfig>,
+}
 
 impl<B> OnResponse<B> for OtelOnResponse {
     fn on_response(self, response: &Response<B>, _latency: Duration, span: &Span) {
-        let status = response.status().as_u16().to_string();
-        span.record("http.status_code", &tracing::field::display(status));
+        let status = response.status();
+        span.record(
+            "http.status_code",
+            tracing::field::display(status.as_u16().to_string()),
+        );
+        span.record("http.response.status_code", status.as_u16());
+        // only declared (so recorded) by the gRPC span, for "Trailers-Only" responses
+        if let Some(grpc_status) = grpc_status(response.headers()) {
+            span.record("rpc.grpc.status_code", grpc_status);
+        }
+        self.config
+            .captured_headers
+            .record_response_headers(response.headers(), span);
 
-        // assume there is no error, if there is `OtelOnFailure` will be called and override this
-        span.record("otel.status_code", "OK");
+        if status.is_client_error()
+            && self.config.failure_classification == FailureClassification::ClientAndServerErrors
+        {
+            span.record("otel.status_code", "ERROR");
+            span.record("error.type", status.as_str());
+        } else {
+            // assume there is no error, if there is `OtelOnFailure` will be called and override this
+            span.record("otel.status_code", "OK");
+        }
     }
 }
 
+fn grpc_status(headers: &HeaderMap) -> Option<i32> {
+    headers.get("grpc-status")?.to_str().ok()?.parse().ok()
+}
+
 /// Callback that [`Trace`] will call when the response body produces a chunk.
 ///
 /// [`Trace`]: tower_http::trace::Trace
@@ -402,8 +964,12 @@ impl<B> OnBodyChunk<B> for OtelOnBodyChunk {
 pub struct OtelOnEos;
 
 impl OnEos for OtelOnEos {
-    #[inline]
-    fn on_eos(self, _trailers: Option<&http::HeaderMap>, _stream_duration: Duration, _span: &Span) {
+    fn on_eos(self, trailers: Option<&http::HeaderMap>, _stream_duration: Duration, span: &Span) {
+        // only called when the status is not in the headers, eg for the streaming gRPC responses,
+        // the gRPC status is then sent in the trailers
+        if let Some(grpc_status) = trailers.and_then(grpc_status) {
+            record_grpc_status(span, grpc_status);
+        }
     }
 }
 
@@ -419,10 +985,12 @@ impl OnFailure<ServerErrorsFailureClass> for OtelOnFailure {
             ServerErrorsFailureClass::StatusCode(status) => {
                 if status.is_server_error() {
                     span.record("otel.status_code", "ERROR");
+                    span.record("error.type", status.as_str());
                 }
             }
             ServerErrorsFailureClass::Error(_) => {
                 span.record("otel.status_code", "ERROR");
+                span.record("error.type", "_OTHER");
             }
         }
     }
@@ -437,29 +1005,60 @@ pub struct OtelOnGrpcFailure;
 impl OnFailure<GrpcFailureClass> for OtelOnGrpcFailure {
     fn on_failure(&mut self, failure: GrpcFailureClass, _latency: Duration, span: &Span) {
         match failure {
-            GrpcFailureClass::Code(code) => {
-                span.record("http.grpc_status", code);
-            }
+            GrpcFailureClass::Code(code) => record_grpc_status(span, code.get()),
             GrpcFailureClass::Error(_) => {
                 span.record("http.grpc_status", 1);
+                span.record("rpc.grpc.status_code", 1);
+                span.record("otel.status_code", "ERROR");
             }
         }
     }
 }
 
+fn record_grpc_status(span: &Span, code: i32) {
+    span.record("http.grpc_status", code);
+    span.record("rpc.grpc.status_code", code);
+    if is_grpc_server_error(code) {
+        span.record("otel.status_code", "ERROR");
+    }
+}
+
+/// The gRPC codes considered as errors on the server side by the [semantic conventions][semconv]
+/// (the other codes are caused by the client).
+///
+/// [semconv]: https://opentelemetry.io/docs/specs/semconv/rpc/grpc/#grpc-status
+fn is_grpc_server_error(code: i32) -> bool {
+    matches!(
+        code,
+        2  // Unknown
+        | 4  // DeadlineExceeded
+        | 12 // Unimplemented
+        | 13 // Internal
+        | 14 // Unavailable
+        | 15 // DataLoss
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use assert2::*;
     use axum::{
         body::Body,
+        extract::ConnectInfo,
         routing::{get, post},
         Router,
     };
+    use futures::future::BoxFuture;
     use http::{Request, StatusCode};
-    use opentelemetry::sdk::propagation::TraceContextPropagator;
+    use opentelemetry::sdk::{
+        export::trace::{ExportResult, SpanData, SpanExporter},
+        propagation::TraceContextPropagator,
+        trace::Sampler,
+    };
     use rstest::*;
     use serde_json::Value;
+    use std::net::SocketAddr;
     use std::sync::mpsc::{self, Receiver, SyncSender};
 
     use tracing_subscriber::{
@@ -470,6 +1069,7 @@ mod tests {
 
     #[rstest]
     #[case("filled_http_route_for_existing_route", "/users/123", &[], 0, false)]
+    // the fallback has no `MatchedPath` (so no "http.route") since axum-0.6.16
     #[case("empty_http_route_for_nonexisting_route", "/idontexist/123", &[], 0, false)]
     #[case("status_code_on_close_for_ok", "/users/123", &[], 1, false)]
     #[case("status_code_on_close_for_error", "/status/500", &[], 1, false)]
@@ -489,31 +1089,7 @@ mod tests {
         #[case] event_idx: usize,
         #[case] is_trace_id_constant: bool,
     ) {
-        let svc = Router::new()
-            .route("/users/:id", get(|| async { StatusCode::OK }))
-            .route(
-                "/status/500",
-                get(|| async { StatusCode::INTERNAL_SERVER_ERROR }),
-            )
-            .route(
-                "/with_child_span",
-                get(|| async {
-                    let span = tracing::span!(tracing::Level::INFO, "my child span");
-                    span.in_scope(|| {
-                        // Any trace events in this closure or code called by it will occur within
-                        // the span.
-                    });
-                    StatusCode::OK
-                }),
-            )
-            .nest(
-                "/nest",
-                Router::new()
-                    .route("/:nest_id", get(|| async {}))
-                    .fallback(|| async { (StatusCode::NOT_FOUND, "inner fallback") }),
-            )
-            .fallback(|| async { (StatusCode::NOT_FOUND, "outer fallback") })
-            .layer(opentelemetry_tracing_layer());
+        let svc = app().layer(opentelemetry_tracing_layer());
         let mut builder = Request::builder();
         for (key, value) in headers.iter() {
             builder = builder.header(*key, *value);
@@ -543,8 +1119,345 @@ mod tests {
         });
     }
 
+    const PEER: &str = "192.0.2.1:4711";
+
+    #[rstest]
+    #[case("span_name_with_route", OtelLayerBuilder::default().with_span_name(SpanNameStrategy::Route), "/users/123", &[], None, 0)]
+    #[case("span_name_with_custom", OtelLayerBuilder::default().with_span_name(SpanNameStrategy::Custom(Arc::new(|method, route| format!("{method} (custom) {route}")))), "/users/123", &[], None, 0)]
+    #[case("client_ip_ignore_x_forwarded_for", OtelLayerBuilder::default().with_client_ip(ClientIpStrategy::ConnectInfo), "/users/123", &[("x-forwarded-for", "127.0.0.1")], None, 0)]
+    #[case("captured_request_headers", OtelLayerBuilder::default().with_request_headers([HeaderName::from_static("x-request-id"), header::AUTHORIZATION, header::ACCEPT]), "/users/123", &[("x-request-id", "abc"), ("authorization", "Bearer secret"), ("accept", "text/html"), ("accept", "application/json")], None, 1)]
+    #[case("captured_response_headers", OtelLayerBuilder::default().with_response_headers([header::CONTENT_TYPE, header::ETAG]), "/idontexist/123", &[], None, 1)]
+    #[case("semconv_stable_on_new", OtelLayerBuilder::default().with_semconv_mode(SemConvMode::Stable), "/users/123?page=2", &[("host", "example.com:8080"), ("user-agent", "tests"), ("x-forwarded-for", "127.0.0.1")], None, 0)]
+    #[case("semconv_stable_on_close_for_error", OtelLayerBuilder::default().with_semconv_mode(SemConvMode::Stable), "/status/500", &[], Some(PEER), 1)]
+    #[case("semconv_dup_on_close", OtelLayerBuilder::default().with_semconv_mode(SemConvMode::Duplicate), "/users/123?page=2", &[("host", "example.com"), ("user-agent", "tests")], Some(PEER), 1)]
+    #[case("client_error_as_failure", OtelLayerBuilder::default().with_failure_classification(FailureClassification::ClientAndServerErrors), "/idontexist/123", &[], None, 1)]
+    #[tokio::test]
+    async fn check_span_event_with_builder(
+        #[case] name: &str,
+        #[case] builder: OtelLayerBuilder,
+        #[case] uri: &str,
+        #[case] headers: &[(&str, &str)],
+        #[case] peer: Option<&str>,
+        #[case] event_idx: usize,
+    ) {
+        let svc = app().layer(builder.build());
+        let mut builder = Request::builder();
+        for (key, value) in headers.iter() {
+            builder = builder.header(*key, *value);
+        }
+        let mut req = builder.uri(uri).body(Body::empty()).unwrap();
+        if let Some(peer) = peer {
+            req.extensions_mut()
+                .insert(ConnectInfo(peer.parse::<SocketAddr>().unwrap()));
+        }
+        let events = span_event_for_request(svc, req).await;
+        insta::assert_yaml_snapshot!(name, events[event_idx], {
+            ".timestamp" => "[timestamp]",
+            ".fields[\"time.busy\"]" => "[duration]",
+            ".fields[\"time.idle\"]" => "[duration]",
+            ".span.trace_id" => "[trace_id]",
+        });
+    }
+
+    #[rstest]
+    #[case("/module.service/endpoint1", Some(("module.service", "endpoint1")))]
+    #[case("/helloworld.Greeter/SayHello", Some(("helloworld.Greeter", "SayHello")))]
+    #[case("/Service/Method", Some(("Service", "Method")))]
+    #[case("/", None)]
+    #[case("/module.service", None)]
+    #[case("/module.service/", None)]
+    #[case("//endpoint1", None)]
+    #[case("/module.service/endpoint1/more", None)]
+    fn test_parse_grpc_path(#[case] path: &str, #[case] expected: Option<(&str, &str)>) {
+        check!(parse_grpc_path(path) == expected);
+    }
+
+    #[rstest]
+    #[case("", SemConvMode::Legacy)]
+    #[case("http", SemConvMode::Stable)]
+    #[case("database, http", SemConvMode::Stable)]
+    #[case("http/dup", SemConvMode::Duplicate)]
+    #[case("http,http/dup", SemConvMode::Duplicate)]
+    #[case("database", SemConvMode::Legacy)]
+    fn test_semconv_mode_from_opt_in(#[case] value: &str, #[case] expected: SemConvMode) {
+        check!(SemConvMode::from_opt_in(value) == expected);
+    }
+
+    #[rstest]
+    #[case("/", "", None, None)]
+    #[case("/", "example.com", Some("example.com"), None)]
+    #[case("/", "example.com:8080", Some("example.com"), Some(8080))]
+    #[case("/", "[::1]:8080", Some("[::1]"), Some(8080))]
+    #[case(
+        "https://example.org:8443/",
+        "example.com",
+        Some("example.org"),
+        Some(8443)
+    )]
+    fn test_server_address_and_port(
+        #[case] uri: &str,
+        #[case] host: &str,
+        #[case] expected_address: Option<&str>,
+        #[case] expected_port: Option<u16>,
+    ) {
+        let uri = uri.parse::<Uri>().unwrap();
+        check!(server_address_and_port(&uri, host) == (expected_address, expected_port));
+    }
+
+    #[rstest]
+    #[case(OtelLayerBuilder::default().with_excluded_path("/health"), "/health", &[], true)]
+    #[case(OtelLayerBuilder::default().with_excluded_path("/health"), "/health?full=true", &[], true)]
+    #[case(OtelLayerBuilder::default().with_excluded_path("/health"), "/users/123", &[], false)]
+    #[case(OtelLayerBuilder::default().with_excluded_path("/nest/*"), "/nest/123", &[], true)]
+    #[case(OtelLayerBuilder::default().with_excluded_route("/users/:id"), "/users/123", &[], true)]
+    #[case(OtelLayerBuilder::default().with_excluded_route("/users/:id"), "/health", &[], false)]
+    #[case(OtelLayerBuilder::default().with_exclusion(|_, _, headers| headers.contains_key("x-probe")), "/users/123", &[("x-probe", "1")], true)]
+    #[case(OtelLayerBuilder::default().with_exclusion(|_, _, headers| headers.contains_key("x-probe")), "/users/123", &[], false)]
+    #[tokio::test]
+    async fn no_span_for_excluded_request(
+        #[case] builder: OtelLayerBuilder,
+        #[case] uri: &str,
+        #[case] headers: &[(&str, &str)],
+        #[case] is_excluded: bool,
+    ) {
+        let svc = app()
+            .route("/health", get(|| async { StatusCode::OK }))
+            .layer(builder.build());
+        let mut builder = Request::builder();
+        for (key, value) in headers.iter() {
+            builder = builder.header(*key, *value);
+        }
+        let req = builder.uri(uri).body(Body::empty()).unwrap();
+        let events = span_event_for_request(svc, req).await;
+        check!(events.is_empty() == is_excluded);
+    }
+
+    #[tokio::test]
+    async fn trace_id_from_configured_id_generator() {
+        use opentelemetry::{
+            sdk::trace::{IdGenerator, RandomIdGenerator},
+            trace::{SpanId, TracerProvider as _},
+        };
+
+        #[derive(Debug)]
+        struct FixedTraceIdGenerator;
+
+        impl IdGenerator for FixedTraceIdGenerator {
+            fn new_trace_id(&self) -> TraceId {
+                TraceId::from_bytes(0x0123456789abcdef0123456789abcdef_u128.to_be_bytes())
+            }
+
+            fn new_span_id(&self) -> SpanId {
+                RandomIdGenerator::default().new_span_id()
+            }
+        }
+
+        let provider = opentelemetry::sdk::trace::TracerProvider::builder()
+            .with_config(
+                opentelemetry::sdk::trace::config().with_id_generator(FixedTraceIdGenerator),
+            )
+            .build();
+        let tracer = provider.tracer("test");
+        let svc = app().layer(
+            OtelLayerBuilder::default()
+                .with_tracer(tracer.clone())
+                .build(),
+        );
+        let req = Request::builder()
+            .uri("/users/123")
+            .body(Body::empty())
+            .unwrap();
+        let events = span_event_for_request_with_tracer(svc, req, tracer).await;
+        // set at the creation of the span
+        check!(events[0]["span"]["trace_id"] == "0123456789abcdef0123456789abcdef");
+        check!(events[1]["span"]["trace_id"] == "0123456789abcdef0123456789abcdef");
+    }
+
+    #[rstest]
+    #[case(&[], None)]
+    #[case(&[("traceparent", "00-b2611246a58fd7ea623d2264c5a1e226-b2c9b811f2f424af-01")], Some("b2c9b811f2f424af"))]
+    #[tokio::test]
+    async fn exported_span_has_no_link_and_only_remote_parent(
+        #[case] headers: &[(&str, &str)],
+        #[case] parent_span_id: Option<&str>,
+    ) {
+        use opentelemetry::trace::{SpanId, TracerProvider as _};
+
+        let exporter = InMemoryExporter::default();
+        let provider = opentelemetry::sdk::trace::TracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let tracer = provider.tracer("test");
+        let svc = app().layer(
+            OtelLayerBuilder::default()
+                .with_tracer(tracer.clone())
+                .build(),
+        );
+        let mut builder = Request::builder();
+        for (key, value) in headers.iter() {
+            builder = builder.header(*key, *value);
+        }
+        let req = builder.uri("/users/123").body(Body::empty()).unwrap();
+        let events = span_event_for_request_with_tracer(svc, req, tracer).await;
+        // flush the exporter
+        drop(provider);
+
+        let spans = exporter.0.lock().unwrap();
+        let_assert!([span] = spans.as_slice());
+        check!(span.links.is_empty());
+        let parent_span_id =
+            parent_span_id.map_or(SpanId::INVALID, |id| SpanId::from_hex(id).unwrap());
+        check!(span.parent_span_id == parent_span_id);
+        check!(events[0]["span"]["trace_id"] == span.span_context.trace_id().to_string());
+        check!(events[1]["span"]["trace_id"] == span.span_context.trace_id().to_string());
+    }
+
+    #[rstest]
+    #[case(Sampler::AlwaysOn, 1)]
+    #[case(Sampler::ParentBased(Box::new(Sampler::AlwaysOn)), 1)]
+    #[case(Sampler::ParentBased(Box::new(Sampler::AlwaysOff)), 0)]
+    #[case(Sampler::TraceIdRatioBased(0.0), 0)]
+    #[tokio::test]
+    async fn root_span_sampled_by_configured_sampler(
+        #[case] sampler: Sampler,
+        #[case] expected_exported: usize,
+    ) {
+        use opentelemetry::trace::TracerProvider as _;
+
+        let exporter = InMemoryExporter::default();
+        let provider = opentelemetry::sdk::trace::TracerProvider::builder()
+            .with_config(opentelemetry::sdk::trace::config().with_sampler(sampler))
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let tracer = provider.tracer("test");
+        let svc = app().layer(
+            OtelLayerBuilder::default()
+                .with_tracer(tracer.clone())
+                .build(),
+        );
+        let req = Request::builder()
+            .uri("/users/123")
+            .body(Body::empty())
+            .unwrap();
+        let events = span_event_for_request_with_tracer(svc, req, tracer).await;
+        drop(provider);
+
+        check!(events[0]["span"]["trace_id"].as_str().map(str::len) == Some(32));
+        check!(exporter.0.lock().unwrap().len() == expected_exported);
+    }
+
+    #[rstest]
+    #[case(SemConvMode::Legacy, "/users/123", 1)]
+    #[case(SemConvMode::Legacy, "/users/456", 0)]
+    #[case(SemConvMode::Stable, "/users/123", 1)]
+    #[case(SemConvMode::Stable, "/users/456", 0)]
+    #[tokio::test]
+    async fn root_span_sampled_by_the_attributes_of_the_request(
+        #[case] mode: SemConvMode,
+        #[case] uri: &str,
+        #[case] expected_exported: usize,
+    ) {
+        use opentelemetry::trace::TracerProvider as _;
+
+        let exporter = InMemoryExporter::default();
+        // the root sampler is the delegate of `ParentBased`, so the decision taken before the
+        // creation of the span is followed
+        let sampler = Sampler::ParentBased(Box::new(GetUser123Sampler));
+        let provider = opentelemetry::sdk::trace::TracerProvider::builder()
+            .with_config(opentelemetry::sdk::trace::config().with_sampler(sampler))
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let tracer = provider.tracer("test");
+        let svc = app().layer(
+            OtelLayerBuilder::default()
+                .with_semconv_mode(mode)
+                .with_tracer(tracer.clone())
+                .build(),
+        );
+        let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+        span_event_for_request_with_tracer(svc, req, tracer).await;
+        drop(provider);
+
+        check!(exporter.0.lock().unwrap().len() == expected_exported);
+    }
+
+    /// Sample only `GET /users/123`, from the attributes given to the sampler.
+    #[derive(Clone, Debug)]
+    struct GetUser123Sampler;
+
+    impl opentelemetry::sdk::trace::ShouldSample for GetUser123Sampler {
+        fn should_sample(
+            &self,
+            _parent_context: Option<&opentelemetry::Context>,
+            _trace_id: TraceId,
+            _name: &str,
+            _span_kind: &SpanKind,
+            attributes: &OrderMap<Key, opentelemetry::Value>,
+            _links: &[opentelemetry::trace::Link],
+            _instrumentation_library: &opentelemetry::InstrumentationLibrary,
+        ) -> opentelemetry::trace::SamplingResult {
+            let attribute = |keys: &[&'static str]| {
+                keys.iter()
+                    .find_map(|key| attributes.get(&Key::from_static_str(key)))
+                    .map(|value| value.as_str().into_owned())
+            };
+            let sampled = attribute(&["http.method", "http.request.method"]).as_deref()
+                == Some("GET")
+                && attribute(&["http.target", "url.path"]).as_deref() == Some("/users/123");
+            opentelemetry::trace::SamplingResult {
+                decision: if sampled {
+                    SamplingDecision::RecordAndSample
+                } else {
+                    SamplingDecision::Drop
+                },
+                attributes: Vec::new(),
+                trace_state: Default::default(),
+            }
+        }
+    }
+
+    #[derive(Clone, Debug, Default)]
+    struct InMemoryExporter(Arc<std::sync::Mutex<Vec<SpanData>>>);
+
+    impl SpanExporter for InMemoryExporter {
+        fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+            self.0.lock().unwrap().extend(batch);
+            Box::pin(std::future::ready(Ok(())))
+        }
+    }
+
+    fn app() -> Router {
+        Router::new()
+            .route("/users/:id", get(|| async { StatusCode::OK }))
+            .route(
+                "/status/500",
+                get(|| async { StatusCode::INTERNAL_SERVER_ERROR }),
+            )
+            .route(
+                "/with_child_span",
+                get(|| async {
+                    let span = tracing::span!(tracing::Level::INFO, "my child span");
+                    span.in_scope(|| {
+                        // Any trace events in this closure or code called by it will occur within
+                        // the span.
+                    });
+                    StatusCode::OK
+                }),
+            )
+            .nest(
+                "/nest",
+                Router::new()
+                    .route("/:nest_id", get(|| async {}))
+                    .fallback(|| async { (StatusCode::NOT_FOUND, "inner fallback") }),
+            )
+            .fallback(|| async { (StatusCode::NOT_FOUND, "outer fallback") })
+    }
+
     #[rstest]
     #[case("grpc_status_code_on_close_for_ok", "/module.service/endpoint1", &[], 1)]
+    #[case("grpc_status_code_on_close_for_streaming_ok", "/module.service/stream", &[("x-grpc-status", "0")], 1)]
+    #[case("grpc_status_code_on_close_for_streaming_client_error", "/module.service/stream", &[("x-grpc-status", "5")], 1)]
+    #[case("grpc_status_code_on_close_for_streaming_server_error", "/module.service/stream", &[("x-grpc-status", "13")], 1)]
     #[tokio::test]
     async fn check_span_event_grpc(
         #[case] name: &str,
@@ -563,6 +1476,24 @@ mod tests {
                         .unwrap()
                 }),
             )
+            .route(
+                "/module.service/stream",
+                post(|headers: HeaderMap| async move {
+                    // the gRPC status is sent in the trailers, after the messages
+                    let grpc_status = headers["x-grpc-status"].clone();
+                    let (mut sender, body) = Body::channel();
+                    tokio::spawn(async move {
+                        sender.send_data("message".into()).await.unwrap();
+                        let mut trailers = HeaderMap::new();
+                        trailers.insert("grpc-status", grpc_status);
+                        sender.send_trailers(trailers).await.unwrap();
+                    });
+                    Response::builder()
+                        .status(StatusCode::OK)
+                        .body(body)
+                        .unwrap()
+                }),
+            )
             .layer(opentelemetry_tracing_layer_grpc());
         let mut builder = Request::builder();
         for (key, value) in headers.iter() {
@@ -582,17 +1513,26 @@ mod tests {
         });
     }
 
-    async fn span_event_for_request(mut router: Router, req: Request<Body>) -> Vec<Value> {
-        use axum::body::HttpBody as _;
-        use tower::{Service, ServiceExt};
-        use tracing_subscriber::layer::SubscriberExt;
-
+    async fn span_event_for_request(router: Router, req: Request<Body>) -> Vec<Value> {
         // setup a non Noop OpenTelemetry tracer to have non-empty trace_id
         let tracer = opentelemetry_otlp::new_pipeline()
             .tracing()
             .with_exporter(opentelemetry_otlp::new_exporter().tonic())
             .install_batch(opentelemetry::runtime::Tokio)
             .unwrap();
+        set_default_tracer(&tracer);
+        span_event_for_request_with_tracer(router, req, tracer).await
+    }
+
+    async fn span_event_for_request_with_tracer(
+        mut router: Router,
+        req: Request<Body>,
+        tracer: opentelemetry::sdk::trace::Tracer,
+    ) -> Vec<Value> {
+        use axum::body::HttpBody as _;
+        use tower::{Service, ServiceExt};
+        use tracing_subscriber::layer::SubscriberExt;
+
         opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
         let otel_layer = tracing_opentelemetry::layer().with_tracer(tracer);
 
@@ -3,6 +3,8 @@ use opentelemetry::{sdk::trace as sdktrace, trace::TraceError};
 use opentelemetry_jaeger::config::agent::AgentPipeline;
 use opentelemetry_semantic_conventions as semcov;
 
+use super::sampler::read_sampler_from_env;
+
 pub fn identity(v: AgentPipeline) -> AgentPipeline {
     v
 }
@@ -12,6 +14,19 @@ pub fn identity(v: AgentPipeline) -> AgentPipeline {
 /// All variables are optional, a full list of accepted options can be found in the
 /// [jaeger variables spec](https://github.com/open-telemetry/opentelemetry-specification/blob/main/specification/sdk-environment-variables.md#jaeger-exporter).
 pub fn init_tracer<F>(resource: Resource, transform: F) -> Result<sdktrace::Tracer, TraceError>
+where
+    F: FnOnce(AgentPipeline) -> AgentPipeline,
+{
+    let sampler = read_sampler_from_env(&resource, false)?;
+    init_tracer_with_sampler(resource, transform, sampler)
+}
+
+/// Like [`init_tracer`], with the `sampler` instead of the one from `OTEL_TRACES_SAMPLER`.
+pub fn init_tracer_with_sampler<F>(
+    resource: Resource,
+    transform: F,
+    sampler: sdktrace::Sampler,
+) -> Result<sdktrace::Tracer, TraceError>
 where
     F: FnOnce(AgentPipeline) -> AgentPipeline,
 {
@@ -22,11 +37,34 @@ where
     if let Some(name) = resource.get(semcov::resource::SERVICE_NAME) {
         pipeline = pipeline.with_service_name(name.to_string());
     }
-    pipeline = pipeline.with_trace_config(
-        sdktrace::config()
-            .with_resource(resource)
-            .with_sampler(sdktrace::Sampler::AlwaysOn),
-    );
+    pipeline = pipeline.with_trace_config(super::trace_config(resource, sampler));
     pipeline = transform(pipeline);
     pipeline.install_batch(opentelemetry::runtime::Tokio)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::SamplerConfig;
+    use assert2::check;
+
+    #[test]
+    fn install_the_sampler() {
+        let sampler = SamplerConfig::TraceIdRatio(0.25)
+            .build(&Resource::empty())
+            .unwrap();
+        // the agent exporter can not be built (the blocking udp socket is rejected by tokio in
+        // debug), so the configured pipeline is checked, before its build fails
+        let mut pipeline = String::new();
+        let tracer = init_tracer_with_sampler(
+            Resource::empty(),
+            |p| {
+                pipeline = format!("{p:?}");
+                p.with_endpoint("invalid_endpoint")
+            },
+            sampler,
+        );
+        check!(tracer.is_err());
+        check!(pipeline.contains("sampler: TraceIdRatioBased(0.25)"));
+    }
+}
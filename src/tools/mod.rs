@@ -3,7 +3,7 @@ use opentelemetry::sdk::propagation::{
     BaggagePropagator, TextMapCompositePropagator, TraceContextPropagator,
 };
 #[cfg(feature = "tracer")]
-use opentelemetry::sdk::trace::Tracer;
+use opentelemetry::sdk::trace::{self as sdktrace, Tracer};
 #[cfg(feature = "tracer")]
 use opentelemetry::sdk::Resource;
 use opentelemetry::trace::TraceError;
@@ -12,12 +12,30 @@ use opentelemetry::trace::TraceError;
 pub mod jaeger;
 #[cfg(feature = "otlp")]
 pub mod otlp;
+#[cfg(feature = "logs")]
+pub mod otlp_logs;
+#[cfg(feature = "prometheus")]
+pub mod prometheus;
 #[cfg(feature = "tracer")]
 pub mod resource;
 #[cfg(feature = "tracer")]
+pub mod sampler;
+#[cfg(feature = "tracer")]
 pub mod stdio;
 #[cfg(feature = "tracing_subscriber_ext")]
 pub mod tracing_subscriber_ext;
+#[cfg(feature = "xray")]
+pub mod xray;
+#[cfg(feature = "zipkin")]
+pub mod zipkin;
+
+/// Boundaries of the histograms used by the meter providers, in milliseconds for
+/// `http.server.duration` (the sdk use the same boundaries for every histograms).
+#[cfg(all(feature = "metrics", any(feature = "otlp", feature = "prometheus")))]
+pub(crate) const HISTOGRAM_BOUNDARIES: [f64; 15] = [
+    0.0, 5.0, 10.0, 25.0, 50.0, 75.0, 100.0, 250.0, 500.0, 750.0, 1000.0, 2500.0, 5000.0, 7500.0,
+    10000.0,
+];
 
 #[cfg(feature = "tracer")]
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -26,23 +44,59 @@ pub enum CollectorKind {
     Otlp,
     #[cfg(feature = "jaeger")]
     Jaeger,
+    #[cfg(feature = "zipkin")]
+    Zipkin,
     Stdout,
     Stderr,
     NoWrite,
 }
 
+/// The configuration of the tracer shared by the pipelines: the resource, the sampler (see
+/// [`sampler::read_sampler_from_env`]), and the X-Ray id generator with the feature `xray` (so the
+/// trace ids can be converted into X-Ray trace ids).
+#[cfg(feature = "tracer")]
+pub(crate) fn trace_config(resource: Resource, sampler: sdktrace::Sampler) -> sdktrace::Config {
+    let config = sdktrace::config()
+        .with_resource(resource)
+        .with_sampler(sampler);
+    #[cfg(feature = "xray")]
+    let config = config.with_id_generator(sdktrace::XrayIdGenerator::default());
+    config
+}
+
 #[cfg(feature = "tracer")]
 #[deprecated(
     since = "0.10.0",
     note = "call `init_tracer` from sub sub package directly"
 )]
 pub fn init_tracer(kind: CollectorKind, resource: Resource) -> Result<Tracer, TraceError> {
+    init_tracer_of_kind(kind, resource, sampler::SamplerConfig::from_env(), false)
+}
+
+/// Install the tracer provider exporting to `kind` (with the default configuration of the
+/// pipeline), an invalid configuration of the sampler (eg from [`sampler::SamplerConfig::from_env`])
+/// fails when `strict_sampler` (see [`sampler::read_sampler_from_env`]).
+#[cfg(feature = "tracer")]
+pub(crate) fn init_tracer_of_kind(
+    kind: CollectorKind,
+    resource: Resource,
+    sampler_config: Result<sampler::SamplerConfig, sampler::SamplerConfigError>,
+    strict_sampler: bool,
+) -> Result<Tracer, TraceError> {
+    let sampler = sampler::sampler_from_config(&resource, sampler_config, strict_sampler)?;
     match kind {
-        CollectorKind::Stdout => stdio::init_tracer(resource, stdio::identity, std::io::stdout()),
-        CollectorKind::Stderr => stdio::init_tracer(resource, stdio::identity, std::io::stderr()),
-        CollectorKind::NoWrite => {
-            stdio::init_tracer(resource, stdio::identity, stdio::WriteNoWhere::default())
+        CollectorKind::Stdout => {
+            stdio::init_tracer_with_sampler(resource, stdio::identity, std::io::stdout(), sampler)
+        }
+        CollectorKind::Stderr => {
+            stdio::init_tracer_with_sampler(resource, stdio::identity, std::io::stderr(), sampler)
         }
+        CollectorKind::NoWrite => stdio::init_tracer_with_sampler(
+            resource,
+            stdio::identity,
+            stdio::WriteNoWhere::default(),
+            sampler,
+        ),
         #[cfg(feature = "otlp")]
         CollectorKind::Otlp => {
             // if let Some(url) = std::env::var_os("OTEL_COLLECTOR_URL")
@@ -50,14 +104,77 @@ pub fn init_tracer(kind: CollectorKind, resource: Resource) -> Result<Tracer, Tr
             // let collector_url = url.to_str().ok_or(TraceError::Other(
             //     anyhow!("failed to parse OTEL_COLLECTOR_URL").into(),
             // ))?;
-            otlp::init_tracer(resource, otlp::identity)
+            otlp::init_tracer_with_sampler(resource, otlp::identity, sampler)
         }
         #[cfg(feature = "jaeger")]
         CollectorKind::Jaeger => {
             // Or "OTEL_EXPORTER_JAEGER_ENDPOINT"
             // or now variable
-            jaeger::init_tracer(resource, jaeger::identity)
+            jaeger::init_tracer_with_sampler(resource, jaeger::identity, sampler)
         }
+        #[cfg(feature = "zipkin")]
+        CollectorKind::Zipkin => {
+            zipkin::init_tracer_with_sampler(resource, zipkin::identity, sampler)
+        }
+    }
+}
+
+/// Install the tracer provider exporting to the exporter selected by the env variable
+/// [OTEL_TRACES_EXPORTER](https://opentelemetry.io/docs/concepts/sdk-configuration/general-sdk-configuration/#otel_traces_exporter).
+/// Default value: `"otlp"`
+/// Accepted values for `OTEL_TRACES_EXPORTER` are:
+///
+/// - "otlp": OTLP (require feature "otlp")
+/// - "jaeger": Jaeger agent (require feature "jaeger")
+/// - "zipkin": Zipkin (require feature "zipkin")
+/// - "console": Standard output
+/// - "none": No export, the traces are still created and propagated
+#[cfg(feature = "tracer")]
+pub fn init_tracer_from_env(resource: Resource) -> Result<Tracer, TraceError> {
+    init_tracer_of_kind(
+        collector_kind_from_env()?,
+        resource,
+        sampler::SamplerConfig::from_env(),
+        false,
+    )
+}
+
+/// Read the exporter of the traces from `OTEL_TRACES_EXPORTER` (see [`init_tracer_from_env`]).
+#[cfg(feature = "tracer")]
+pub(crate) fn collector_kind_from_env() -> Result<CollectorKind, TraceError> {
+    let value_from_env =
+        std::env::var("OTEL_TRACES_EXPORTER").unwrap_or_else(|_| "otlp".to_string());
+    let kind = collector_kind_from_string(&value_from_env.trim().to_lowercase())?;
+    tracing::debug!(target: "otel::setup", OTEL_TRACES_EXPORTER = ?kind);
+    Ok(kind)
+}
+
+#[cfg(feature = "tracer")]
+fn collector_kind_from_string(v: &str) -> Result<CollectorKind, TraceError> {
+    match v {
+        #[cfg(feature = "otlp")]
+        "otlp" => Ok(CollectorKind::Otlp),
+        #[cfg(not(feature = "otlp"))]
+        "otlp" => Err(TraceError::from(
+            "unsupported exporter from env OTEL_TRACES_EXPORTER: 'otlp', try to enable compile feature 'otlp'"
+        )),
+        #[cfg(feature = "jaeger")]
+        "jaeger" => Ok(CollectorKind::Jaeger),
+        #[cfg(not(feature = "jaeger"))]
+        "jaeger" => Err(TraceError::from(
+            "unsupported exporter from env OTEL_TRACES_EXPORTER: 'jaeger', try to enable compile feature 'jaeger'"
+        )),
+        #[cfg(feature = "zipkin")]
+        "zipkin" => Ok(CollectorKind::Zipkin),
+        #[cfg(not(feature = "zipkin"))]
+        "zipkin" => Err(TraceError::from(
+            "unsupported exporter from env OTEL_TRACES_EXPORTER: 'zipkin', try to enable compile feature 'zipkin'"
+        )),
+        "console" => Ok(CollectorKind::Stdout),
+        "none" => Ok(CollectorKind::NoWrite),
+        unknown => Err(TraceError::from(format!(
+            "unsupported exporter from env OTEL_TRACES_EXPORTER: '{unknown}'"
+        ))),
     }
 }
 
@@ -78,7 +195,13 @@ pub fn init_tracer(kind: CollectorKind, resource: Resource) -> Result<Tracer, Tr
 pub fn init_propagator() -> Result<(), TraceError> {
     let value_from_env =
         std::env::var("OTEL_PROPAGATORS").unwrap_or_else(|_| "tracecontext,baggage".to_string());
-    let propagators: Vec<(Box<dyn TextMapPropagator + Send + Sync>, String)> = value_from_env
+    init_propagator_from_str(&value_from_env)
+}
+
+/// Configure the global propagator from a comma-separated list of propagators,
+/// with the same values than `OTEL_PROPAGATORS` (see [`init_propagator`]).
+pub(crate) fn init_propagator_from_str(value: &str) -> Result<(), TraceError> {
+    let propagators: Vec<(Box<dyn TextMapPropagator + Send + Sync>, String)> = value
         .split(',')
         .map(|s| {
             let name = s.trim().to_lowercase();
@@ -172,6 +295,7 @@ pub fn find_current_trace_id() -> Option<String> {
 #[cfg(test)]
 #[cfg(feature = "tracer")]
 mod tests {
+    use super::CollectorKind;
     use assert2::*;
     use axum::{
         body::Body,
@@ -183,6 +307,30 @@ mod tests {
     use tower::ServiceExt; // for `oneshot` and `ready`
     use tracing::subscriber::DefaultGuard;
 
+    #[cfg(feature = "otlp")]
+    #[rstest::rstest]
+    #[case(false, true)]
+    #[case(true, false)]
+    #[tokio::test]
+    async fn init_tracer_of_kind_with_an_invalid_sampler(
+        #[case] strict_sampler: bool,
+        #[case] expected_ok: bool,
+    ) {
+        use super::sampler::SamplerConfig;
+        use opentelemetry::sdk::Resource;
+
+        // like `OTEL_TRACES_SAMPLER=traceidratio` & `OTEL_TRACES_SAMPLER_ARG=2` (the environment
+        // is not modified, it is read by the other tests)
+        let sampler_config = SamplerConfig::parse("traceidratio", Some("2"));
+        let tracer = super::init_tracer_of_kind(
+            CollectorKind::Otlp,
+            Resource::empty(),
+            sampler_config,
+            strict_sampler,
+        );
+        check!(tracer.is_ok() == expected_ok);
+    }
+
     fn init_tracing() -> Result<DefaultGuard, BoxError> {
         use tracing_subscriber::filter::EnvFilter;
         use tracing_subscriber::fmt::format::FmtSpan;
@@ -301,4 +449,22 @@ mod tests {
         // dbg!(std::env::var("OTEL_PROPAGATORS"));
         // let_assert!(Err(_) = init_tracing());
     }
+
+    #[rstest::rstest]
+    #[case("console", Some(CollectorKind::Stdout))]
+    #[case("none", Some(CollectorKind::NoWrite))]
+    #[case("xxxxxx", None)]
+    fn test_collector_kind_from_string(
+        #[case] value: &str,
+        #[case] expected: Option<CollectorKind>,
+    ) {
+        check!(super::collector_kind_from_string(value).ok() == expected);
+    }
+
+    #[test]
+    #[cfg(not(feature = "jaeger"))]
+    fn collector_kind_from_string_failed_on_missing_feature() {
+        let_assert!(Err(err) = super::collector_kind_from_string("jaeger"));
+        check!(err.to_string().contains("compile feature 'jaeger'"));
+    }
 }
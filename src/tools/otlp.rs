@@ -1,99 +1,605 @@
-use std::str::FromStr;
-
+#[cfg(feature = "metrics")]
+use opentelemetry::metrics::MetricsError;
+#[cfg(feature = "metrics")]
+use opentelemetry::sdk::export::metrics::aggregation::Temporality;
 use opentelemetry::sdk::propagation::TraceContextPropagator;
 use opentelemetry::sdk::trace::{Sampler, Tracer};
 use opentelemetry::sdk::Resource;
 use opentelemetry::trace::TraceError;
 use opentelemetry_otlp::SpanExporterBuilder;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use super::sampler::read_sampler_from_env;
 
-pub fn identity(v: opentelemetry_otlp::OtlpTracePipeline) -> opentelemetry_otlp::OtlpTracePipeline {
+/// No transformation, usable as `transform` of [`init_tracer`] (and of `init_meter`).
+pub fn identity<T>(v: T) -> T {
     v
 }
 
-// see https://opentelemetry.io/docs/reference/specification/protocol/exporter/
+/// Setup an OTLP pipeline (spans exported by batch) with the sampler from `OTEL_TRACES_SAMPLER`.
+///
+/// Configured via the environment variables (see the
+/// [exporter spec](https://opentelemetry.io/docs/reference/specification/protocol/exporter/)),
+/// the `OTEL_EXPORTER_OTLP_TRACES_*` variables fallback to the `OTEL_EXPORTER_OTLP_*` ones:
+///
+/// - `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT` & `OTEL_EXPORTER_OTLP_TRACES_PROTOCOL`: the url of the
+///   collector and the protocol (`grpc` or `http/protobuf`), when only one of them is defined
+///   the other is inferred
+/// - `OTEL_EXPORTER_OTLP_TRACES_HEADERS`: the headers (or grpc metadata) of the exports, as
+///   comma-separated list of `key=value` (percent-encoded)
+/// - `OTEL_EXPORTER_OTLP_TRACES_TIMEOUT`: the maximum duration of an export, in milliseconds
+///   (default: 10000)
+/// - `OTEL_EXPORTER_OTLP_TRACES_COMPRESSION`: `none` (default) or `gzip`, with the feature
+///   `otlp_gzip`; only the `http/protobuf` protocol compresses the traces, the grpc exporter of
+///   `opentelemetry-otlp` 0.11 can not compress (a warning is logged and the traces are sent
+///   uncompressed)
+/// - `OTEL_EXPORTER_OTLP_TRACES_CERTIFICATE`: the path of the certificate (PEM) to verify the
+///   collector (feature `otlp_tls`)
+/// - `OTEL_EXPORTER_OTLP_TRACES_CLIENT_CERTIFICATE` & `OTEL_EXPORTER_OTLP_TRACES_CLIENT_KEY`: the
+///   paths of the certificate & private key (PEM) of the client, for mTLS (feature `otlp_tls`)
 pub fn init_tracer<F>(resource: Resource, transform: F) -> Result<Tracer, TraceError>
 where
     F: FnOnce(opentelemetry_otlp::OtlpTracePipeline) -> opentelemetry_otlp::OtlpTracePipeline,
 {
-    use opentelemetry_otlp::WithExportConfig;
+    let sampler = read_sampler_from_env(&resource, false)?;
+    init_tracer_with_sampler(resource, transform, sampler)
+}
 
+/// Like [`init_tracer`], with the `sampler` instead of the one from `OTEL_TRACES_SAMPLER`.
+///
+/// ```no_run
+/// use axum_tracing_opentelemetry::{otlp, resource::DetectResource, sampler};
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), opentelemetry::trace::TraceError> {
+/// let resource = DetectResource::default().build();
+/// // fail on an invalid OTEL_TRACES_SAMPLER / OTEL_TRACES_SAMPLER_ARG
+/// let sampler = sampler::read_sampler_from_env(&resource, true)?;
+/// let tracer = otlp::init_tracer_with_sampler(resource, otlp::identity, sampler)?;
+/// # let _ = tracer;
+/// # Ok(())
+/// # }
+/// ```
+pub fn init_tracer_with_sampler<F>(
+    resource: Resource,
+    transform: F,
+    sampler: Sampler,
+) -> Result<Tracer, TraceError>
+where
+    F: FnOnce(opentelemetry_otlp::OtlpTracePipeline) -> opentelemetry_otlp::OtlpTracePipeline,
+{
     opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
-    let (protocol, endpoint) = infer_protocol_and_endpoint(read_protocol_and_endpoint_from_env());
-    tracing::debug!(target: "otel::setup", OTEL_EXPORTER_OTLP_TRACES_ENDPOINT = endpoint);
-    tracing::debug!(target: "otel::setup", OTEL_EXPORTER_OTLP_TRACES_PROTOCOL = protocol);
-    let exporter: SpanExporterBuilder = match protocol.as_str() {
-        "http/protobuf" => opentelemetry_otlp::new_exporter()
-            .http()
-            .with_endpoint(endpoint)
-            .into(),
-        _ => opentelemetry_otlp::new_exporter()
-            .tonic()
-            .with_endpoint(endpoint)
-            .into(),
-    };
+    let exporter = read_span_exporter_from_env()?;
 
     let mut pipeline = opentelemetry_otlp::new_pipeline()
         .tracing()
         .with_exporter(exporter)
-        .with_trace_config(
-            opentelemetry::sdk::trace::config()
-                .with_resource(resource)
-                .with_sampler(read_sampler_from_env()),
-        );
+        .with_trace_config(super::trace_config(resource, sampler));
     pipeline = transform(pipeline);
     pipeline.install_batch(opentelemetry::runtime::Tokio)
 }
 
-fn read_protocol_and_endpoint_from_env() -> (Option<String>, Option<String>) {
-    let maybe_endpoint = std::env::var("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT")
+fn read_span_exporter_from_env() -> Result<SpanExporterBuilder, TraceError> {
+    let (protocol, endpoint) =
+        infer_protocol_and_endpoint(read_protocol_and_endpoint_from_env("TRACES"));
+    tracing::debug!(target: "otel::setup", OTEL_EXPORTER_OTLP_TRACES_ENDPOINT = endpoint);
+    tracing::debug!(target: "otel::setup", OTEL_EXPORTER_OTLP_TRACES_PROTOCOL = protocol);
+    let headers = read_headers_from_env("TRACES");
+    tracing::debug!(target: "otel::setup", OTEL_EXPORTER_OTLP_TRACES_HEADERS = ?headers.keys());
+    let timeout = read_timeout_from_env("TRACES");
+    tracing::debug!(target: "otel::setup", OTEL_EXPORTER_OTLP_TRACES_TIMEOUT = ?timeout);
+    let compression = read_compression_from_env("TRACES")?;
+    let tls = read_tls_config_from_env("TRACES")?;
+    span_exporter(&protocol, endpoint, headers, timeout, compression, tls)
+}
+
+fn span_exporter(
+    protocol: &str,
+    endpoint: String,
+    headers: HashMap<String, String>,
+    timeout: Duration,
+    compression: Compression,
+    tls: Option<TlsConfig>,
+) -> Result<SpanExporterBuilder, TraceError> {
+    use opentelemetry_otlp::WithExportConfig;
+
+    check_tls_support(&tls)?;
+    let exporter = match protocol {
+        "http/protobuf" => {
+            let exporter = opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(endpoint)
+                .with_headers(headers)
+                .with_timeout(timeout);
+            let exporter = match (compression, tls) {
+                #[cfg(feature = "otlp_gzip")]
+                (Compression::Gzip, tls) => {
+                    exporter.with_http_client(GzipHttpClient(http_client(timeout, tls)?))
+                }
+                #[cfg(feature = "otlp_tls")]
+                (Compression::None, Some(tls)) => {
+                    exporter.with_http_client(tls.http_client(timeout)?)
+                }
+                _ => exporter,
+            };
+            exporter.into()
+        }
+        _ => {
+            if compression == Compression::Gzip {
+                tracing::warn!(
+                    target: "otel::setup",
+                    "OTEL_EXPORTER_OTLP_TRACES_COMPRESSION=gzip is not supported by the grpc \
+                    exporter of opentelemetry-otlp 0.11, the traces are sent uncompressed (use \
+                    the protocol 'http/protobuf' to compress them)"
+                );
+            }
+            // the channel is created here, else `opentelemetry-otlp` reads the timeout from
+            // `OTEL_EXPORTER_OTLP_TRACES_TIMEOUT` as seconds (and ignores the TLS configuration)
+            let channel = grpc_channel(endpoint.clone(), timeout, tls)?;
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint)
+                .with_channel(channel)
+                .with_metadata(metadata_from_headers(headers))
+                .with_timeout(timeout)
+                .into()
+        }
+    };
+    Ok(exporter)
+}
+
+/// Create the (lazily connected) channel to the grpc `endpoint`, should be called from a
+/// tokio runtime.
+pub(crate) fn grpc_channel(
+    endpoint: String,
+    timeout: Duration,
+    tls: Option<TlsConfig>,
+) -> Result<tonic::transport::Channel, TraceError> {
+    check_tls_support(&tls)?;
+    let channel = tonic::transport::Endpoint::from_shared(endpoint)
+        .map_err(|err| TraceError::Other(Box::new(err)))?
+        .timeout(timeout);
+    #[cfg(feature = "otlp_tls")]
+    let channel = match tls {
+        Some(tls) => channel
+            .tls_config(tls.tonic_config())
+            .map_err(|err| TraceError::Other(Box::new(err)))?,
+        None => channel,
+    };
+    Ok(channel.connect_lazy())
+}
+
+fn check_compression_support(compression: Compression) -> Result<(), TraceError> {
+    if cfg!(not(feature = "otlp_gzip")) && compression == Compression::Gzip {
+        return Err(TraceError::from(
+            "gzip compression from OTEL_EXPORTER_OTLP_*COMPRESSION, \
+            try to enable compile feature 'otlp_gzip'",
+        ));
+    }
+    Ok(())
+}
+
+fn check_tls_support(tls: &Option<TlsConfig>) -> Result<(), TraceError> {
+    if cfg!(not(feature = "otlp_tls")) && tls.is_some() {
+        return Err(TraceError::from(
+            "TLS configuration from OTEL_EXPORTER_OTLP_*CERTIFICATE & OTEL_EXPORTER_OTLP_*CLIENT_KEY, \
+            try to enable compile feature 'otlp_tls'",
+        ));
+    }
+    Ok(())
+}
+
+/// Export the metrics to the OTLP endpoint, and install the meter provider as global meter provider.
+///
+/// Configured via the environment variables:
+///
+/// - `OTEL_EXPORTER_OTLP_METRICS_ENDPOINT` & `OTEL_EXPORTER_OTLP_METRICS_PROTOCOL`, with the same
+///   fallbacks and inference than for the traces (only `grpc` is supported for the metrics),
+///   when none is defined `grpc` on `http://localhost:4317` (opentelemetry-otlp 0.11 only
+///   provides a grpc exporter for the metrics, so `http/protobuf` is rejected)
+/// - `OTEL_EXPORTER_OTLP_METRICS_HEADERS`, `OTEL_EXPORTER_OTLP_METRICS_TIMEOUT` and the TLS
+///   certificates, with the same fallbacks than for the traces (the TLS requires the feature
+///   `otlp_tls`); note that opentelemetry-otlp reads `OTEL_EXPORTER_OTLP_METRICS_TIMEOUT` itself
+///   in seconds (and not in milliseconds), prefer `OTEL_EXPORTER_OTLP_TIMEOUT`
+/// - `OTEL_METRIC_EXPORT_INTERVAL`: the interval between two exports, in milliseconds
+///   (default: 60000)
+/// - `OTEL_METRIC_EXPORT_TIMEOUT`: the maximum duration of an export, in milliseconds
+///   (default: 30000)
+/// - `OTEL_EXPORTER_OTLP_METRICS_TEMPORALITY_PREFERENCE`: `cumulative` (default) or `delta`
+///
+/// Use the same resource as the tracer (eg from `DetectResource`) to correlate the signals.
+#[cfg(feature = "metrics")]
+pub fn init_meter<F>(
+    resource: Resource,
+    transform: F,
+) -> Result<opentelemetry::sdk::metrics::controllers::BasicController, MetricsError>
+where
+    F: FnOnce(opentelemetry_otlp::TonicExporterBuilder) -> opentelemetry_otlp::TonicExporterBuilder,
+{
+    use opentelemetry::sdk::{export::metrics::aggregation, metrics::selectors};
+
+    let (protocol, endpoint) =
+        infer_grpc_protocol_and_endpoint(read_protocol_and_endpoint_from_env("METRICS"));
+    tracing::debug!(target: "otel::setup", OTEL_EXPORTER_OTLP_METRICS_ENDPOINT = endpoint);
+    tracing::debug!(target: "otel::setup", OTEL_EXPORTER_OTLP_METRICS_PROTOCOL = protocol);
+    if protocol != "grpc" {
+        return Err(MetricsError::Other(format!(
+            "unsupported protocol '{protocol}' to export the metrics (only 'grpc' is supported), \
+            set OTEL_EXPORTER_OTLP_METRICS_PROTOCOL or OTEL_EXPORTER_OTLP_METRICS_ENDPOINT"
+        )));
+    }
+    let headers = read_headers_from_env("METRICS");
+    tracing::debug!(target: "otel::setup", OTEL_EXPORTER_OTLP_METRICS_HEADERS = ?headers.keys());
+    let export_timeout = read_timeout_from_env("METRICS");
+    tracing::debug!(target: "otel::setup", OTEL_EXPORTER_OTLP_METRICS_TIMEOUT = ?export_timeout);
+    let tls =
+        read_tls_config_from_env("METRICS").map_err(|err| MetricsError::Other(err.to_string()))?;
+    let exporter = metrics_exporter(endpoint, headers, export_timeout, tls)?;
+    let interval = read_millis_from_env("OTEL_METRIC_EXPORT_INTERVAL", 60_000);
+    let timeout = read_millis_from_env("OTEL_METRIC_EXPORT_TIMEOUT", 30_000);
+    let temporality = read_temporality_from_env();
+
+    opentelemetry_otlp::new_pipeline()
+        .metrics(
+            selectors::simple::histogram(super::HISTOGRAM_BOUNDARIES),
+            aggregation::constant_temporality_selector(temporality),
+            opentelemetry::runtime::Tokio,
+        )
+        .with_exporter(transform(exporter))
+        .with_resource(resource)
+        .with_period(interval)
+        .with_timeout(timeout)
+        .build()
+}
+
+#[cfg(feature = "metrics")]
+fn metrics_exporter(
+    endpoint: String,
+    headers: HashMap<String, String>,
+    timeout: Duration,
+    tls: Option<TlsConfig>,
+) -> Result<opentelemetry_otlp::TonicExporterBuilder, MetricsError> {
+    use opentelemetry_otlp::WithExportConfig;
+
+    check_tls_support(&tls).map_err(|err| MetricsError::Other(err.to_string()))?;
+    // the metrics exporter of opentelemetry-otlp 0.11 ignores `with_channel` (it creates its own
+    // channel), so the TLS & the timeout are given through the builder
+    let exporter = opentelemetry_otlp::new_exporter()
+        .tonic()
+        .with_endpoint(endpoint)
+        .with_timeout(timeout)
+        .with_metadata(metadata_from_headers(headers));
+    #[cfg(feature = "otlp_tls")]
+    let exporter = match tls {
+        Some(tls) => exporter.with_tls_config(tls.tonic_config()),
+        None => exporter,
+    };
+    Ok(exporter)
+}
+
+#[cfg(any(feature = "metrics", feature = "logs"))]
+pub(crate) fn read_millis_from_env(key: &str, default: u64) -> std::time::Duration {
+    let millis = std::env::var(key)
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(default);
+    tracing::debug!(target: "otel::setup", "{key}={millis}");
+    std::time::Duration::from_millis(millis)
+}
+
+#[cfg(feature = "metrics")]
+fn read_temporality_from_env() -> Temporality {
+    let value =
+        std::env::var("OTEL_EXPORTER_OTLP_METRICS_TEMPORALITY_PREFERENCE").unwrap_or_default();
+    let temporality = temporality_from_str(&value);
+    tracing::debug!(target: "otel::setup", OTEL_EXPORTER_OTLP_METRICS_TEMPORALITY_PREFERENCE = ?temporality);
+    temporality
+}
+
+#[cfg(feature = "metrics")]
+fn temporality_from_str(value: &str) -> Temporality {
+    if value.trim().eq_ignore_ascii_case("delta") {
+        Temporality::Delta
+    } else {
+        Temporality::Cumulative
+    }
+}
+
+/// Read `OTEL_EXPORTER_OTLP_{signal}_PROTOCOL` & `OTEL_EXPORTER_OTLP_{signal}_ENDPOINT`,
+/// with fallback to `OTEL_EXPORTER_OTLP_PROTOCOL` & `OTEL_EXPORTER_OTLP_ENDPOINT`.
+pub(crate) fn read_protocol_and_endpoint_from_env(
+    signal: &str,
+) -> (Option<String>, Option<String>) {
+    let maybe_endpoint = std::env::var(format!("OTEL_EXPORTER_OTLP_{signal}_ENDPOINT"))
         .or_else(|_| std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT"))
         .ok();
-    let maybe_protocol = std::env::var("OTEL_EXPORTER_OTLP_TRACES_PROTOCOL")
+    let maybe_protocol = std::env::var(format!("OTEL_EXPORTER_OTLP_{signal}_PROTOCOL"))
         .or_else(|_| std::env::var("OTEL_EXPORTER_OTLP_PROTOCOL"))
         .ok();
     (maybe_protocol, maybe_endpoint)
 }
 
-/// see <https://opentelemetry.io/docs/reference/specification/sdk-environment-variables/#general-sdk-configuration>
-/// TODO log error and infered sampler
-fn read_sampler_from_env() -> Sampler {
-    let mut name = std::env::var("OTEL_TRACES_SAMPLER")
+/// Read `OTEL_EXPORTER_OTLP_{signal}_{key}`, with fallback to `OTEL_EXPORTER_OTLP_{key}`.
+fn read_signal_var_from_env(signal: &str, key: &str) -> Option<String> {
+    std::env::var(format!("OTEL_EXPORTER_OTLP_{signal}_{key}"))
+        .or_else(|_| std::env::var(format!("OTEL_EXPORTER_OTLP_{key}")))
         .ok()
+}
+
+pub(crate) fn read_headers_from_env(signal: &str) -> HashMap<String, String> {
+    read_signal_var_from_env(signal, "HEADERS")
+        .map(|v| headers_from_str(&v))
         .unwrap_or_default()
-        .to_lowercase();
-    let v = match name.as_str() {
-        "always_on" => Sampler::AlwaysOn,
-        "always_off" => Sampler::AlwaysOff,
-        "traceidratio" => Sampler::TraceIdRatioBased(read_sampler_arg_from_env(1f64)),
-        "parentbased_always_on" => Sampler::ParentBased(Box::new(Sampler::AlwaysOn)),
-        "parentbased_always_off" => Sampler::ParentBased(Box::new(Sampler::AlwaysOff)),
-        "parentbased_traceidratio" => Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
-            read_sampler_arg_from_env(1f64),
-        ))),
-        "jaeger_remote" => todo!("unsupported: OTEL_TRACES_SAMPLER='jaeger_remote'"),
-        "xray" => todo!("unsupported: OTEL_TRACES_SAMPLER='xray'"),
+}
+
+pub(crate) fn read_timeout_from_env(signal: &str) -> Duration {
+    timeout_from_str(read_signal_var_from_env(signal, "TIMEOUT").as_deref())
+}
+
+/// Read the compression from `OTEL_EXPORTER_OTLP_{signal}_COMPRESSION`, fails on `gzip` without
+/// the feature `otlp_gzip`.
+pub(crate) fn read_compression_from_env(signal: &str) -> Result<Compression, TraceError> {
+    let compression = compression_from_str(
+        signal,
+        read_signal_var_from_env(signal, "COMPRESSION").as_deref(),
+    );
+    tracing::debug!(target: "otel::setup", "OTEL_EXPORTER_OTLP_{signal}_COMPRESSION: {compression:?}");
+    check_compression_support(compression)?;
+    Ok(compression)
+}
+
+fn compression_from_str(signal: &str, value: Option<&str>) -> Compression {
+    let Some(value) = value else {
+        return Compression::None;
+    };
+    match value.trim().to_lowercase().as_str() {
+        "" | "none" => Compression::None,
+        "gzip" => Compression::Gzip,
+        _ => {
+            tracing::warn!(target: "otel::setup", "invalid OTEL_EXPORTER_OTLP_{signal}_COMPRESSION: '{value}' (expected 'gzip' or 'none'), the export is uncompressed");
+            Compression::None
+        }
+    }
+}
+
+/// The compression of the exports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Compression {
+    None,
+    Gzip,
+}
+
+#[cfg(feature = "logs")]
+impl Compression {
+    /// The encoding of the grpc messages, `None` when uncompressed.
+    pub(crate) fn grpc_encoding(self) -> Option<tonic::codec::CompressionEncoding> {
+        match self {
+            Compression::None => None,
+            #[cfg(feature = "otlp_gzip")]
+            Compression::Gzip => Some(tonic::codec::CompressionEncoding::Gzip),
+            // rejected by `read_compression_from_env`
+            #[cfg(not(feature = "otlp_gzip"))]
+            Compression::Gzip => None,
+        }
+    }
+
+    /// Compress the body of a http request, with the value of its `Content-Encoding`.
+    pub(crate) fn http_body(
+        self,
+        body: Vec<u8>,
+    ) -> std::io::Result<(Vec<u8>, Option<&'static str>)> {
+        match self {
+            #[cfg(feature = "otlp_gzip")]
+            Compression::Gzip => Ok((gzip(&body)?, Some("gzip"))),
+            // gzip is rejected by `read_compression_from_env` without the feature `otlp_gzip`
+            _ => Ok((body, None)),
+        }
+    }
+}
+
+/// Create the http client of the exports (`http/protobuf`), with the TLS configuration.
+#[cfg(any(feature = "logs", feature = "otlp_gzip"))]
+pub(crate) fn http_client(
+    timeout: Duration,
+    tls: Option<TlsConfig>,
+) -> Result<reqwest::Client, TraceError> {
+    check_tls_support(&tls)?;
+    match tls {
+        #[cfg(feature = "otlp_tls")]
+        Some(tls) => tls.http_client(timeout),
+        _ => reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .map_err(|err| TraceError::Other(Box::new(err))),
+    }
+}
+
+/// Compress the body of a request (for `Content-Encoding: gzip`).
+#[cfg(feature = "otlp_gzip")]
+pub(crate) fn gzip(body: &[u8]) -> std::io::Result<Vec<u8>> {
+    use std::io::Write;
+
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(body)?;
+    encoder.finish()
+}
+
+/// Send the requests of the http exporter with the body compressed by gzip (the exporter of
+/// `opentelemetry-otlp` 0.11 has no option for it).
+#[cfg(feature = "otlp_gzip")]
+#[derive(Debug)]
+struct GzipHttpClient<C>(C);
+
+#[cfg(feature = "otlp_gzip")]
+#[async_trait::async_trait]
+impl<C: opentelemetry_http::HttpClient> opentelemetry_http::HttpClient for GzipHttpClient<C> {
+    async fn send(
+        &self,
+        request: http::Request<Vec<u8>>,
+    ) -> Result<http::Response<opentelemetry_http::Bytes>, opentelemetry_http::HttpError> {
+        let (mut parts, body) = request.into_parts();
+        parts.headers.insert(
+            http::header::CONTENT_ENCODING,
+            http::HeaderValue::from_static("gzip"),
+        );
+        self.0
+            .send(http::Request::from_parts(parts, gzip(&body)?))
+            .await
+    }
+}
+
+/// The certificates (PEM) to connect to the collector via TLS.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TlsConfig {
+    /// the certificate of the authority to verify the collector
+    certificate: Option<Vec<u8>>,
+    /// the certificate & the private key of the client (mTLS)
+    client_identity: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+#[cfg(feature = "otlp_tls")]
+impl TlsConfig {
+    fn tonic_config(self) -> tonic::transport::ClientTlsConfig {
+        use tonic::transport::{Certificate, ClientTlsConfig, Identity};
+
+        let mut config = ClientTlsConfig::new();
+        if let Some(certificate) = self.certificate {
+            config = config.ca_certificate(Certificate::from_pem(certificate));
+        }
+        if let Some((certificate, key)) = self.client_identity {
+            config = config.identity(Identity::from_pem(certificate, key));
+        }
+        config
+    }
+
+    fn http_client(self, timeout: Duration) -> Result<reqwest::Client, TraceError> {
+        let to_trace_error = |err: reqwest::Error| TraceError::Other(Box::new(err));
+        let mut builder = reqwest::Client::builder().use_rustls_tls().timeout(timeout);
+        if let Some(certificate) = self.certificate {
+            builder = builder.add_root_certificate(
+                reqwest::Certificate::from_pem(&certificate).map_err(to_trace_error)?,
+            );
+        }
+        if let Some((certificate, key)) = self.client_identity {
+            // reqwest expects the certificate & the private key in the same PEM
+            let pem = [certificate, key].join(&b'\n');
+            builder = builder.identity(reqwest::Identity::from_pem(&pem).map_err(to_trace_error)?);
+        }
+        builder.build().map_err(to_trace_error)
+    }
+}
+
+pub(crate) fn read_tls_config_from_env(signal: &str) -> Result<Option<TlsConfig>, TraceError> {
+    tls_config_from_pems(
+        signal,
+        read_signal_file_from_env(signal, "CERTIFICATE")?,
+        read_signal_file_from_env(signal, "CLIENT_CERTIFICATE")?,
+        read_signal_file_from_env(signal, "CLIENT_KEY")?,
+    )
+}
+
+/// Read the file at the path from `OTEL_EXPORTER_OTLP_{signal}_{key}`, with fallback to
+/// `OTEL_EXPORTER_OTLP_{key}`.
+fn read_signal_file_from_env(signal: &str, key: &str) -> Result<Option<Vec<u8>>, TraceError> {
+    let maybe_var_and_path = [
+        format!("OTEL_EXPORTER_OTLP_{signal}_{key}"),
+        format!("OTEL_EXPORTER_OTLP_{key}"),
+    ]
+    .into_iter()
+    .find_map(|var| std::env::var(&var).ok().map(|path| (var, path)));
+    match maybe_var_and_path {
+        Some((var, path)) if !path.trim().is_empty() => {
+            tracing::debug!(target: "otel::setup", "{var}={path}");
+            read_file(&var, path.trim()).map(Some)
+        }
+        _ => Ok(None),
+    }
+}
+
+fn read_file(var: &str, path: &str) -> Result<Vec<u8>, TraceError> {
+    std::fs::read(path).map_err(|err| {
+        TraceError::from(format!("failed to read the file '{path}' of {var}: {err}"))
+    })
+}
+
+fn tls_config_from_pems(
+    signal: &str,
+    certificate: Option<Vec<u8>>,
+    client_certificate: Option<Vec<u8>>,
+    client_key: Option<Vec<u8>>,
+) -> Result<Option<TlsConfig>, TraceError> {
+    let client_identity = match (client_certificate, client_key) {
+        (Some(certificate), Some(key)) => Some((certificate, key)),
+        (None, None) => None,
         _ => {
-            name = "parentbased_always_on".to_string();
-            Sampler::ParentBased(Box::new(Sampler::AlwaysOn))
+            return Err(TraceError::from(format!(
+                "both OTEL_EXPORTER_OTLP_{signal}_CLIENT_CERTIFICATE & \
+                OTEL_EXPORTER_OTLP_{signal}_CLIENT_KEY (or OTEL_EXPORTER_OTLP_CLIENT_CERTIFICATE & \
+                OTEL_EXPORTER_OTLP_CLIENT_KEY) should be defined for the client authentication"
+            )))
         }
     };
-    tracing::debug!(target: "otel::setup", OTEL_TRACES_SAMPLER = ?name);
-    v
+    Ok(
+        (certificate.is_some() || client_identity.is_some()).then_some(TlsConfig {
+            certificate,
+            client_identity,
+        }),
+    )
 }
 
-fn read_sampler_arg_from_env<T>(default: T) -> T
-where
-    T: FromStr + Copy + std::fmt::Debug,
-{
-    //TODO Log for invalid value (how to log)
-    let v = std::env::var("OTEL_TRACES_SAMPLER_ARG")
-        .map(|s| T::from_str(&s).unwrap_or(default))
-        .unwrap_or(default);
-    tracing::debug!(target: "otel::setup", OTEL_TRACES_SAMPLER_ARG = ?v);
-    v
+/// Parse the comma-separated list of `key=value` (percent-encoded), the invalid entries are
+/// ignored (with a warning).
+fn headers_from_str(value: &str) -> HashMap<String, String> {
+    let decode = |v: &str| {
+        percent_encoding::percent_decode_str(v.trim())
+            .decode_utf8()
+            .map(|v| v.into_owned())
+    };
+    value
+        .split(',')
+        .filter(|entry| !entry.trim().is_empty())
+        .filter_map(|entry| {
+            let header = entry
+                .split_once('=')
+                .and_then(|(k, v)| Some((decode(k).ok()?, decode(v).ok()?)))
+                .filter(|(k, _)| !k.is_empty());
+            if header.is_none() {
+                tracing::warn!(target: "otel::setup", "ignore invalid header (expected 'key=value', percent-encoded): '{entry}'");
+            }
+            header
+        })
+        .collect()
+}
+
+pub(crate) fn metadata_from_headers(
+    headers: HashMap<String, String>,
+) -> tonic::metadata::MetadataMap {
+    use tonic::metadata::{MetadataKey, MetadataMap, MetadataValue};
+
+    let mut metadata = MetadataMap::with_capacity(headers.len());
+    for (key, value) in headers {
+        match (
+            MetadataKey::from_bytes(key.to_lowercase().as_bytes()),
+            MetadataValue::try_from(value.as_str()),
+        ) {
+            (Ok(key), Ok(value)) => {
+                metadata.insert(key, value);
+            }
+            _ => {
+                tracing::warn!(target: "otel::setup", "ignore invalid grpc metadata: '{key}'")
+            }
+        }
+    }
+    metadata
+}
+
+fn timeout_from_str(value: Option<&str>) -> Duration {
+    let millis = value.and_then(|v| v.trim().parse().ok()).unwrap_or(10_000);
+    Duration::from_millis(millis)
 }
 
-fn infer_protocol_and_endpoint(
+pub(crate) fn infer_protocol_and_endpoint(
     (maybe_protocol, maybe_endpoint): (Option<String>, Option<String>),
 ) -> (String, String) {
     let protocol = maybe_protocol.unwrap_or_else(|| {
@@ -116,12 +622,44 @@ fn infer_protocol_and_endpoint(
     (protocol, endpoint)
 }
 
+/// Like [`infer_protocol_and_endpoint`], but `grpc` on `http://localhost:4317` when neither the
+/// protocol nor the endpoint is defined (the default of the metrics & the logs).
+#[cfg(any(feature = "metrics", feature = "logs"))]
+pub(crate) fn infer_grpc_protocol_and_endpoint(
+    protocol_and_endpoint: (Option<String>, Option<String>),
+) -> (String, String) {
+    match protocol_and_endpoint {
+        (None, None) => ("grpc".to_string(), "http://localhost:4317".to_string()), //Devskim: ignore DS137138
+        protocol_and_endpoint => infer_protocol_and_endpoint(protocol_and_endpoint),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::sampler::SamplerConfig;
     use assert2::assert;
     use rstest::*;
 
+    #[tokio::test]
+    async fn install_the_trace_config() {
+        let resource = Resource::new([opentelemetry::KeyValue::new(
+            "deployment.environment",
+            "test",
+        )]);
+        let sampler = SamplerConfig::TraceIdRatio(0.25).build(&resource).unwrap();
+        let tracer = init_tracer_with_sampler(resource, identity, sampler).unwrap();
+        assert2::let_assert!(Some(provider) = tracer.provider());
+        assert!(format!("{:?}", provider.config().sampler) == "TraceIdRatioBased(0.25)");
+        assert!(
+            provider
+                .config()
+                .resource
+                .get("deployment.environment".into())
+                == Some("test".into())
+        );
+    }
+
     #[rstest]
     #[case(None, None, "http/protobuf", "http://localhost:4318")] //Devskim: ignore DS137138
     #[case(Some("http/protobuf"), None, "http/protobuf", "http://localhost:4318")] //Devskim: ignore DS137138
@@ -158,4 +696,479 @@ mod tests {
             )) == (expected_protocol.to_string(), expected_endpoint.to_string())
         );
     }
+
+    #[cfg(any(feature = "metrics", feature = "logs"))]
+    #[rstest]
+    #[case(None, None, "grpc", "http://localhost:4317")] //Devskim: ignore DS137138
+    #[case(Some("http/protobuf"), None, "http/protobuf", "http://localhost:4318")] //Devskim: ignore DS137138
+    #[case(
+        None,
+        Some("https://examples.com:4318"),
+        "http/protobuf",
+        "https://examples.com:4318"
+    )]
+    #[case(
+        None,
+        Some("https://examples.com:4317"),
+        "grpc",
+        "https://examples.com:4317"
+    )]
+    fn test_infer_grpc_protocol_and_endpoint(
+        #[case] protocol: Option<&str>,
+        #[case] endpoint: Option<&str>,
+        #[case] expected_protocol: &str,
+        #[case] expected_endpoint: &str,
+    ) {
+        assert!(
+            infer_grpc_protocol_and_endpoint((
+                protocol.map(|s| s.to_string()),
+                endpoint.map(|s| s.to_string())
+            )) == (expected_protocol.to_string(), expected_endpoint.to_string())
+        );
+    }
+
+    #[cfg(feature = "metrics")]
+    // `stop` blocks until the end of the exporter task (run by another worker)
+    #[tokio::test(flavor = "multi_thread")]
+    async fn init_meter_with_the_default_env() {
+        // no test defines the OTLP variables
+        assert!(std::env::vars().all(|(key, _)| !key.starts_with("OTEL_EXPORTER_OTLP")));
+        let controller = init_meter(Resource::empty(), identity);
+        assert2::let_assert!(Ok(controller) = controller);
+        // nothing recorded, so nothing to export to the (missing) collector
+        assert!(controller.stop(&opentelemetry::Context::current()).is_ok());
+    }
+
+    #[rstest]
+    #[case("", &[])]
+    #[case("api-key=secret", &[("api-key", "secret")])]
+    #[case(" api-key = secret , x-tenant=acme ", &[("api-key", "secret"), ("x-tenant", "acme")])]
+    #[case("authorization=Basic%20dXNlcjpwYXNz", &[("authorization", "Basic dXNlcjpwYXNz")])]
+    #[case("key=a%3Db%2Cc", &[("key", "a=b,c")])]
+    #[case("key=a=b", &[("key", "a=b")])]
+    #[case("key=", &[("key", "")])]
+    #[case("invalid,,=value,key=value", &[("key", "value")])]
+    #[case("key=%FF", &[])]
+    fn test_headers_from_str(#[case] value: &str, #[case] expected: &[(&str, &str)]) {
+        let expected: HashMap<String, String> = expected
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        assert!(headers_from_str(value) == expected);
+    }
+
+    #[test]
+    fn test_metadata_from_headers() {
+        let metadata = metadata_from_headers(headers_from_str(
+            "API-Key=secret,invalid key=value,x-tenant=acme",
+        ));
+        assert!(metadata.len() == 2);
+        assert!(metadata.get("api-key").map(|v| v.to_str().ok()) == Some(Some("secret")));
+        assert!(metadata.get("x-tenant").map(|v| v.to_str().ok()) == Some(Some("acme")));
+    }
+
+    #[rstest]
+    #[case(None, 10_000)]
+    #[case(Some(""), 10_000)]
+    #[case(Some("invalid"), 10_000)]
+    #[case(Some("500"), 500)]
+    #[case(Some(" 30000 "), 30_000)]
+    fn test_timeout_from_str(#[case] value: Option<&str>, #[case] expected_millis: u64) {
+        assert!(timeout_from_str(value) == Duration::from_millis(expected_millis));
+    }
+
+    #[test]
+    fn read_file_failed_on_unreadable_file() {
+        assert2::let_assert!(
+            Err(err) = read_file("OTEL_EXPORTER_OTLP_CERTIFICATE", "/not/found/ca.pem")
+        );
+        assert!(err.to_string().starts_with(
+            "failed to read the file '/not/found/ca.pem' of OTEL_EXPORTER_OTLP_CERTIFICATE: "
+        ));
+    }
+
+    #[rstest]
+    #[case(None, None, None, "none")]
+    #[case(Some("ca"), None, None, "ca")]
+    #[case(None, Some("cert"), Some("key"), "cert+key")]
+    #[case(Some("ca"), Some("cert"), Some("key"), "ca,cert+key")]
+    #[case(Some("ca"), Some("cert"), None, "error")]
+    #[case(None, None, Some("key"), "error")]
+    fn test_tls_config_from_pems(
+        #[case] certificate: Option<&str>,
+        #[case] client_certificate: Option<&str>,
+        #[case] client_key: Option<&str>,
+        #[case] expected: &str,
+    ) {
+        let pem = |v: &str| v.as_bytes().to_vec();
+        let text = |v: &[u8]| String::from_utf8_lossy(v).into_owned();
+        let actual = match tls_config_from_pems(
+            "TRACES",
+            certificate.map(pem),
+            client_certificate.map(pem),
+            client_key.map(pem),
+        ) {
+            Err(_) => "error".to_string(),
+            Ok(None) => "none".to_string(),
+            Ok(Some(config)) => config
+                .certificate
+                .map(|c| text(&c))
+                .into_iter()
+                .chain(
+                    config
+                        .client_identity
+                        .map(|(c, k)| format!("{}+{}", text(&c), text(&k))),
+                )
+                .collect::<Vec<_>>()
+                .join(","),
+        };
+        assert!(actual == expected);
+    }
+
+    #[cfg(not(feature = "otlp_tls"))]
+    #[test]
+    fn span_exporter_failed_with_tls_config_without_feature() {
+        let tls = TlsConfig {
+            certificate: Some(b"ca".to_vec()),
+            client_identity: None,
+        };
+        let endpoint = "https://localhost:4317".to_string();
+        assert2::let_assert!(
+            Err(err) = span_exporter(
+                "grpc",
+                endpoint,
+                HashMap::new(),
+                Duration::from_secs(1),
+                Compression::None,
+                Some(tls)
+            )
+        );
+        assert!(err
+            .to_string()
+            .contains("try to enable compile feature 'otlp_tls'"));
+    }
+
+    #[rstest]
+    #[case(None, Compression::None)]
+    #[case(Some(""), Compression::None)]
+    #[case(Some("none"), Compression::None)]
+    #[case(Some("gzip"), Compression::Gzip)]
+    #[case(Some(" GZip "), Compression::Gzip)]
+    #[case(Some("br"), Compression::None)]
+    fn test_compression_from_str(#[case] value: Option<&str>, #[case] expected: Compression) {
+        assert!(compression_from_str("TRACES", value) == expected);
+    }
+
+    #[cfg(not(feature = "otlp_gzip"))]
+    #[test]
+    fn gzip_failed_without_feature() {
+        assert2::let_assert!(Err(err) = check_compression_support(Compression::Gzip));
+        assert!(err
+            .to_string()
+            .contains("try to enable compile feature 'otlp_gzip'"));
+    }
+
+    // the channel is lazily connected from the tokio runtime
+    #[tokio::test]
+    async fn span_exporter_fallback_to_no_compression_with_grpc_and_gzip() {
+        assert2::let_assert!(
+            Ok(SpanExporterBuilder::Tonic(_)) = span_exporter(
+                "grpc",
+                "http://localhost:4317".to_string(),
+                HashMap::new(),
+                Duration::from_secs(1),
+                Compression::Gzip,
+                None
+            )
+        );
+    }
+
+    #[cfg(feature = "otlp_gzip")]
+    #[tokio::test]
+    async fn export_with_gzip_over_http() {
+        use hyper::{service::service_fn, Body, Request, Response};
+        use opentelemetry::sdk::export::trace::SpanExporter;
+        use std::io::Read;
+        use std::sync::{Arc, Mutex};
+
+        // a stand-in of a collector, recording the encoding & the decompressed body
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}/v1/traces", listener.local_addr().unwrap()); //Devskim: ignore DS137138
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let service = service_fn(move |req: Request<Body>| {
+                let recorded = recorded.clone();
+                async move {
+                    let encoding = req.headers().get("content-encoding").cloned();
+                    let body = hyper::body::to_bytes(req.into_body()).await?;
+                    let mut decompressed = Vec::new();
+                    let decoded = flate2::read::GzDecoder::new(body.as_ref())
+                        .read_to_end(&mut decompressed)
+                        .is_ok();
+                    recorded.lock().unwrap().push((encoding, decoded));
+                    Ok::<_, hyper::Error>(Response::new(Body::empty()))
+                }
+            });
+            hyper::server::conn::Http::new()
+                .serve_connection(stream, service)
+                .await
+        });
+
+        let builder = span_exporter(
+            "http/protobuf",
+            endpoint,
+            HashMap::new(),
+            Duration::from_secs(5),
+            Compression::Gzip,
+            None,
+        )
+        .unwrap();
+        let mut exporter = builder.build_span_exporter().unwrap();
+        assert!(exporter.export(vec![]).await.is_ok());
+
+        let requests = requests.lock().unwrap();
+        assert2::let_assert!([(Some(encoding), decoded)] = requests.as_slice());
+        assert!(encoding == "gzip");
+        assert!(*decoded);
+    }
+
+    #[cfg(feature = "otlp_tls")]
+    #[rstest]
+    #[tokio::test]
+    async fn export_to_collector_with_mtls(
+        #[values("grpc", "http/protobuf")] protocol: &str,
+        #[values(true, false)] with_client_identity: bool,
+    ) {
+        use opentelemetry::sdk::export::trace::SpanExporter;
+
+        let pki = tls_stub::Pki::generate();
+        let (endpoint, requests) = tls_stub::start_collector(&pki).await;
+        // the http exporter of `opentelemetry-otlp` 0.11 sends to the endpoint as is
+        let endpoint = match protocol {
+            "grpc" => endpoint,
+            _ => format!("{endpoint}/v1/traces"),
+        };
+        let tls = TlsConfig {
+            certificate: Some(pki.ca_certificate.into_bytes()),
+            client_identity: with_client_identity.then(|| {
+                (
+                    pki.client_certificate.into_bytes(),
+                    pki.client_key.into_bytes(),
+                )
+            }),
+        };
+        let builder = span_exporter(
+            protocol,
+            endpoint,
+            HashMap::new(),
+            Duration::from_secs(5),
+            Compression::None,
+            Some(tls),
+        )
+        .unwrap();
+        let mut exporter = builder.build_span_exporter().unwrap();
+        let result = exporter.export(vec![]).await;
+
+        // the collector requires a certificate from the client
+        assert!(result.is_ok() == with_client_identity);
+        let expected_requests = match (protocol, with_client_identity) {
+            (_, false) => vec![],
+            ("grpc", true) => vec!["/opentelemetry.proto.collector.trace.v1.TraceService/Export"],
+            (_, true) => vec!["/v1/traces"],
+        };
+        assert!(*requests.lock().unwrap() == expected_requests);
+    }
+
+    #[cfg(all(feature = "metrics", feature = "otlp_tls"))]
+    // `stop` blocks until the end of the exporter task (run by another worker)
+    #[tokio::test(flavor = "multi_thread")]
+    async fn export_metrics_to_collector_with_mtls() {
+        use opentelemetry::metrics::MeterProvider;
+        use opentelemetry::sdk::{export::metrics::aggregation, metrics::selectors};
+
+        let pki = tls_stub::Pki::generate();
+        let (endpoint, requests) = tls_stub::start_collector(&pki).await;
+        let tls = TlsConfig {
+            certificate: Some(pki.ca_certificate.into_bytes()),
+            client_identity: Some((
+                pki.client_certificate.into_bytes(),
+                pki.client_key.into_bytes(),
+            )),
+        };
+        let exporter =
+            metrics_exporter(endpoint, HashMap::new(), Duration::from_secs(5), Some(tls)).unwrap();
+        let controller = opentelemetry_otlp::new_pipeline()
+            .metrics(
+                selectors::simple::inexpensive(),
+                aggregation::cumulative_temporality_selector(),
+                opentelemetry::runtime::Tokio,
+            )
+            .with_exporter(exporter)
+            .build()
+            .unwrap();
+        let cx = opentelemetry::Context::current();
+        let counter = controller
+            .versioned_meter("test", None, None)
+            .u64_counter("requests")
+            .init();
+        counter.add(&cx, 1, &[]);
+        assert!(controller.stop(&cx).is_ok());
+
+        // the exporter of opentelemetry-otlp sends the request from a spawned task
+        for _ in 0..50 {
+            if !requests.lock().unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        // the first tick of the controller can export too
+        let requests = requests.lock().unwrap();
+        assert!(!requests.is_empty());
+        assert!(requests
+            .iter()
+            .all(|path| path == "/opentelemetry.proto.collector.metrics.v1.MetricsService/Export"));
+    }
+
+    #[cfg(all(feature = "metrics", not(feature = "otlp_tls")))]
+    #[test]
+    fn metrics_exporter_failed_with_tls_without_feature() {
+        let tls = TlsConfig {
+            certificate: Some(b"ca".to_vec()),
+            client_identity: None,
+        };
+        assert2::let_assert!(
+            Err(err) = metrics_exporter(
+                "http://localhost:4317".to_string(),
+                HashMap::new(),
+                Duration::from_secs(1),
+                Some(tls)
+            )
+        );
+        assert!(err
+            .to_string()
+            .contains("try to enable compile feature 'otlp_tls'"));
+    }
+
+    /// A stand-in of a collector requiring mTLS, with the certificates generated for the test.
+    #[cfg(feature = "otlp_tls")]
+    mod tls_stub {
+        use hyper::{service::service_fn, Body, HeaderMap, Request, Response};
+        use rcgen::{BasicConstraints, Certificate, CertificateParams, IsCa};
+        use std::sync::{Arc, Mutex};
+        use tokio_rustls::rustls;
+
+        pub struct Pki {
+            ca: Certificate,
+            pub ca_certificate: String,
+            pub client_certificate: String,
+            pub client_key: String,
+        }
+
+        impl Pki {
+            pub fn generate() -> Self {
+                let mut params = CertificateParams::new(vec![]);
+                params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+                let ca = Certificate::from_params(params).unwrap();
+                let client =
+                    Certificate::from_params(CertificateParams::new(vec!["client".into()]))
+                        .unwrap();
+                Pki {
+                    ca_certificate: ca.serialize_pem().unwrap(),
+                    client_certificate: client.serialize_pem_with_signer(&ca).unwrap(),
+                    client_key: client.serialize_private_key_pem(),
+                    ca,
+                }
+            }
+        }
+
+        /// Start the collector on `localhost`, returns its endpoint & the paths of the requests
+        /// received from authenticated clients.
+        pub async fn start_collector(pki: &Pki) -> (String, Arc<Mutex<Vec<String>>>) {
+            let server =
+                Certificate::from_params(CertificateParams::new(vec!["localhost".into()])).unwrap();
+            let mut roots = rustls::RootCertStore::empty();
+            roots
+                .add(&rustls::Certificate(pki.ca.serialize_der().unwrap()))
+                .unwrap();
+            let mut config = rustls::ServerConfig::builder()
+                .with_safe_defaults()
+                .with_client_cert_verifier(
+                    rustls::server::AllowAnyAuthenticatedClient::new(roots).boxed(),
+                )
+                .with_single_cert(
+                    vec![rustls::Certificate(
+                        server.serialize_der_with_signer(&pki.ca).unwrap(),
+                    )],
+                    rustls::PrivateKey(server.serialize_private_key_der()),
+                )
+                .unwrap();
+            config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+            let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let endpoint = format!(
+                "https://localhost:{}",
+                listener.local_addr().unwrap().port()
+            );
+            let requests = Arc::new(Mutex::new(Vec::new()));
+            let recorded = requests.clone();
+            tokio::spawn(async move {
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    // the handshake fails when the client has no certificate
+                    let Ok(stream) = acceptor.accept(stream).await else {
+                        continue;
+                    };
+                    let recorded = recorded.clone();
+                    let service = service_fn(move |req: Request<Body>| {
+                        recorded.lock().unwrap().push(req.uri().path().to_owned());
+                        async move { Ok::<_, hyper::Error>(export_response(&req)) }
+                    });
+                    tokio::spawn(
+                        hyper::server::conn::Http::new().serve_connection(stream, service),
+                    );
+                }
+            });
+            (endpoint, requests)
+        }
+
+        /// An empty `ExportTraceServiceResponse`.
+        fn export_response(req: &Request<Body>) -> Response<Body> {
+            let is_grpc = req
+                .headers()
+                .get("content-type")
+                .is_some_and(|v| v.as_bytes().starts_with(b"application/grpc"));
+            if !is_grpc {
+                return Response::builder()
+                    .header("content-type", "application/x-protobuf")
+                    .body(Body::empty())
+                    .unwrap();
+            }
+            let (mut sender, body) = Body::channel();
+            tokio::spawn(async move {
+                // a message without compression & of length 0
+                sender.send_data(vec![0u8; 5].into()).await?;
+                let mut trailers = HeaderMap::new();
+                trailers.insert("grpc-status", "0".parse().unwrap());
+                sender.send_trailers(trailers).await
+            });
+            Response::builder()
+                .header("content-type", "application/grpc")
+                .body(body)
+                .unwrap()
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    #[rstest]
+    #[case("", Temporality::Cumulative)]
+    #[case("cumulative", Temporality::Cumulative)]
+    #[case("delta", Temporality::Delta)]
+    #[case(" Delta ", Temporality::Delta)]
+    #[case("lowmemory", Temporality::Cumulative)]
+    fn test_temporality_from_str(#[case] value: &str, #[case] expected: Temporality) {
+        assert!(temporality_from_str(value) == expected);
+    }
 }
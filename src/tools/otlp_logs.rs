@@ -0,0 +1,711 @@
+//! Export the `tracing` events as OTLP log records.
+//!
+//! The opentelemetry sdk has no logs pipeline (yet), so the records are built from the events
+//! by [`OtlpLogsLayer`] and exported (by batch, from a background task) with the OTLP gRPC client
+//! or via `http/protobuf`.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::BoxError;
+use opentelemetry::sdk::trace::Tracer;
+use opentelemetry::sdk::Resource;
+use opentelemetry::trace::{TraceError, TraceFlags};
+use opentelemetry_proto::tonic::collector::logs::v1::{
+    logs_service_client::LogsServiceClient, ExportLogsServiceRequest,
+};
+use opentelemetry_proto::tonic::common::v1::{AnyValue, InstrumentationLibrary, KeyValue};
+use opentelemetry_proto::tonic::logs::v1::{
+    InstrumentationLibraryLogs, LogRecord, ResourceLogs, SeverityNumber,
+};
+use opentelemetry_proto::tonic::resource::v1::Resource as ProtoResource;
+use tokio::sync::{mpsc, Notify};
+use tonic::metadata::MetadataMap;
+use tonic::transport::Channel;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+use super::otlp::{
+    grpc_channel, http_client, infer_grpc_protocol_and_endpoint, metadata_from_headers,
+    read_compression_from_env, read_headers_from_env, read_millis_from_env,
+    read_protocol_and_endpoint_from_env, read_timeout_from_env, read_tls_config_from_env,
+    Compression, TlsConfig,
+};
+use super::tracing_subscriber_ext::find_trace_context;
+
+/// Events of the crates used to export the logs, they are not exported
+/// (to not feed the export with its own logs).
+const EXCLUDED_TARGETS: [&str; 4] = ["h2", "hyper", "tonic", "tower"];
+
+tokio::task_local! {
+    // set on the task exporting the logs, to ignore the events emitted during the export
+    static EXPORTING: ();
+}
+
+/// Create the layer exporting the events as OTLP log records, with the `resource`
+/// (use the resource of the tracer, eg from `DetectResource`, to correlate the signals).
+///
+/// Should be called from a tokio runtime (the export is done by a spawned task).
+///
+/// Configured via the environment variables:
+///
+/// - `OTEL_EXPORTER_OTLP_LOGS_ENDPOINT` & `OTEL_EXPORTER_OTLP_LOGS_PROTOCOL`, with the same
+///   fallbacks and inference than for the traces (`grpc` or `http/protobuf`), when none is
+///   defined `grpc` on `http://localhost:4317`, with `http/protobuf` the path `/v1/logs` is
+///   appended to the endpoint unless it comes from `OTEL_EXPORTER_OTLP_LOGS_ENDPOINT`
+/// - `OTEL_EXPORTER_OTLP_LOGS_HEADERS`, `OTEL_EXPORTER_OTLP_LOGS_TIMEOUT`,
+///   `OTEL_EXPORTER_OTLP_LOGS_CERTIFICATE`, `OTEL_EXPORTER_OTLP_LOGS_CLIENT_CERTIFICATE` &
+///   `OTEL_EXPORTER_OTLP_LOGS_CLIENT_KEY`: like for the traces
+///   (see [`init_tracer`](super::otlp::init_tracer))
+/// - `OTEL_EXPORTER_OTLP_LOGS_COMPRESSION`: `none` (default) or `gzip` (with the feature
+///   `otlp_gzip`, the grpc messages or the http bodies are compressed)
+/// - `OTEL_BLRP_SCHEDULE_DELAY`: the delay between two exports, in milliseconds (default: 1000)
+/// - `OTEL_BLRP_EXPORT_TIMEOUT`: the maximum duration of an export (including the retries of
+///   the connection), in milliseconds (default: 30000)
+/// - `OTEL_BLRP_MAX_QUEUE_SIZE`: the maximum number of records waiting to be exported,
+///   the records are dropped when the queue is full (default: 2048)
+/// - `OTEL_BLRP_MAX_EXPORT_BATCH_SIZE`: the maximum number of records by export (default: 512)
+pub fn init_layer(resource: Resource) -> Result<OtlpLogsLayer, BoxError> {
+    let (protocol, endpoint) =
+        infer_grpc_protocol_and_endpoint(read_protocol_and_endpoint_from_env("LOGS"));
+    tracing::debug!(target: "otel::setup", OTEL_EXPORTER_OTLP_LOGS_ENDPOINT = endpoint);
+    tracing::debug!(target: "otel::setup", OTEL_EXPORTER_OTLP_LOGS_PROTOCOL = protocol);
+    let headers = read_headers_from_env("LOGS");
+    tracing::debug!(target: "otel::setup", OTEL_EXPORTER_OTLP_LOGS_HEADERS = ?headers.keys());
+    let timeout = read_timeout_from_env("LOGS");
+    tracing::debug!(target: "otel::setup", OTEL_EXPORTER_OTLP_LOGS_TIMEOUT = ?timeout);
+    let compression = read_compression_from_env("LOGS")?;
+    let tls = read_tls_config_from_env("LOGS")?;
+    let config = BatchConfig {
+        schedule_delay: read_millis_from_env("OTEL_BLRP_SCHEDULE_DELAY", 1_000),
+        max_batch_size: read_size_from_env("OTEL_BLRP_MAX_EXPORT_BATCH_SIZE", 512),
+    };
+    let export_timeout = read_millis_from_env("OTEL_BLRP_EXPORT_TIMEOUT", 30_000);
+    let max_queue_size = read_size_from_env("OTEL_BLRP_MAX_QUEUE_SIZE", 2048);
+
+    let runtime = tokio::runtime::Handle::try_current()?;
+    let _enter = runtime.enter();
+    let exporter = LogsExporter {
+        client: logs_client(&protocol, endpoint, headers, timeout, compression, tls)?,
+        resource: to_proto_resource(&resource),
+        timeout: export_timeout,
+    };
+    let (sender, receiver) = mpsc::channel(max_queue_size);
+    let (shutdown, stopped) = Shutdown::new();
+    runtime.spawn(EXPORTING.scope(
+        (),
+        export_loop(receiver, shutdown.clone(), stopped, exporter, config),
+    ));
+    Ok(OtlpLogsLayer {
+        sender,
+        shutdown,
+        tracer: None,
+    })
+}
+
+/// Create the client of the collector for the `protocol`, should be called from a tokio runtime.
+fn logs_client(
+    protocol: &str,
+    endpoint: String,
+    headers: HashMap<String, String>,
+    timeout: Duration,
+    compression: Compression,
+    tls: Option<TlsConfig>,
+) -> Result<LogsClient, TraceError> {
+    match protocol {
+        "grpc" => {
+            let client = LogsServiceClient::new(grpc_channel(endpoint, timeout, tls)?);
+            let client = match compression.grpc_encoding() {
+                Some(encoding) => client.send_compressed(encoding),
+                None => client,
+            };
+            Ok(LogsClient::Grpc {
+                client,
+                metadata: metadata_from_headers(headers),
+            })
+        }
+        "http/protobuf" => Ok(LogsClient::Http {
+            client: http_client(timeout, tls)?,
+            endpoint: http_logs_endpoint(endpoint),
+            headers,
+            compression,
+        }),
+        _ => Err(TraceError::from(format!(
+            "unsupported protocol '{protocol}' to export the logs (expected 'grpc' or \
+            'http/protobuf'), set OTEL_EXPORTER_OTLP_LOGS_PROTOCOL or OTEL_EXPORTER_OTLP_PROTOCOL"
+        ))),
+    }
+}
+
+/// The url of the export via `http/protobuf`: `OTEL_EXPORTER_OTLP_LOGS_ENDPOINT` as is, else
+/// the (default) base url with the path `/v1/logs` (like the other OTLP exporters).
+fn http_logs_endpoint(endpoint: String) -> String {
+    if std::env::var("OTEL_EXPORTER_OTLP_LOGS_ENDPOINT").is_ok() {
+        endpoint
+    } else {
+        format!("{}/v1/logs", endpoint.trim_end_matches('/'))
+    }
+}
+
+/// Layer sending the events to the exporter task, see [`init_layer`].
+///
+/// - the severity is mapped from the level of the event
+/// - the body is the message of the event, the other fields are the attributes (with
+///   `code.namespace`, `code.filepath` & `code.lineno`)
+/// - `trace_id` & `span_id` are the ones of the current span (when created by the
+///   `OpenTelemetryLayer` of `tracing-opentelemetry`), the `flags` are the sampling decision
+///   of the span (decided by the tracer when the span is not sampled yet, see
+///   [`OtlpLogsLayer::with_tracer`])
+#[derive(Clone, Debug)]
+pub struct OtlpLogsLayer {
+    sender: mpsc::Sender<LogRecord>,
+    shutdown: Arc<Shutdown>,
+    tracer: Option<Tracer>,
+}
+
+impl OtlpLogsLayer {
+    /// The tracer of the `OpenTelemetryLayer`, to sample the spans not sampled yet
+    /// (default: the tracer installed by `init_subscribers`).
+    pub fn with_tracer(mut self, tracer: Tracer) -> Self {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    /// Export the records in the queue and stop the exporter task (the queue is closed, so the
+    /// next records are dropped), block until the end of the export or at most `timeout`.
+    ///
+    /// Returns `false` when the export is still running after `timeout` (the exporter task ends
+    /// with it, or when its tokio runtime is dropped).
+    ///
+    /// Should not be called from an async context (eg call it from a dedicated thread).
+    pub fn shutdown_blocking(&self, timeout: Duration) -> bool {
+        self.shutdown.requested.notify_one();
+        let stopped = self
+            .shutdown
+            .stopped
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        // disconnected when the exporter task is over (done, or dropped with its runtime)
+        !matches!(
+            stopped.recv_timeout(timeout),
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout)
+        )
+    }
+}
+
+/// The request to stop the exporter task, sent aside of the (maybe full) queue of records.
+#[derive(Debug)]
+struct Shutdown {
+    requested: Notify,
+    /// disconnected when the exporter task is over
+    stopped: Mutex<std::sync::mpsc::Receiver<()>>,
+}
+
+impl Shutdown {
+    /// The shared request, and the sender to move into the exporter task.
+    fn new() -> (Arc<Self>, std::sync::mpsc::Sender<()>) {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let shutdown = Shutdown {
+            requested: Notify::new(),
+            stopped: Mutex::new(receiver),
+        };
+        (Arc::new(shutdown), sender)
+    }
+}
+
+impl<S> Layer<S> for OtlpLogsLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let meta = event.metadata();
+        if EXPORTING.try_with(|_| ()).is_ok() || is_excluded_target(meta.target()) {
+            return;
+        }
+        let mut visitor = LogRecordVisitor::default();
+        event.record(&mut visitor);
+        let mut attributes = visitor.attributes;
+        if let Some(module_path) = meta.module_path() {
+            attributes.push(key_value("code.namespace", module_path.to_owned().into()));
+        }
+        if let Some(file) = meta.file() {
+            attributes.push(key_value("code.filepath", file.to_owned().into()));
+        }
+        if let Some(line) = meta.line() {
+            attributes.push(key_value("code.lineno", i64::from(line).into()));
+        }
+        let now = to_nanos(SystemTime::now());
+        let mut record = LogRecord {
+            time_unix_nano: now,
+            observed_time_unix_nano: now,
+            severity_number: severity_number(meta.level()) as i32,
+            severity_text: meta.level().as_str().to_owned(),
+            body: visitor.body,
+            attributes,
+            ..Default::default()
+        };
+        if let Some((trace_id, span_id, flags)) = ctx
+            .event_span(event)
+            .and_then(|span| find_trace_context(&span, self.tracer.as_ref()))
+        {
+            record.trace_id = trace_id.to_bytes().to_vec();
+            record.span_id = span_id.to_bytes().to_vec();
+            record.flags = u32::from((flags & TraceFlags::SAMPLED).to_u8());
+        }
+        // the record is dropped when the queue is full (or the exporter stopped)
+        let _ = self.sender.try_send(record);
+    }
+}
+
+struct BatchConfig {
+    schedule_delay: Duration,
+    max_batch_size: usize,
+}
+
+/// `_stopped` is dropped at the end of the task (or with the task, when the runtime is dropped),
+/// to unblock [`OtlpLogsLayer::shutdown_blocking`].
+async fn export_loop(
+    mut receiver: mpsc::Receiver<LogRecord>,
+    shutdown: Arc<Shutdown>,
+    _stopped: std::sync::mpsc::Sender<()>,
+    mut exporter: LogsExporter,
+    config: BatchConfig,
+) {
+    let mut batch = Vec::with_capacity(config.max_batch_size);
+    let mut deadline = tokio::time::Instant::now() + config.schedule_delay;
+    loop {
+        let stop = tokio::select! {
+            received = tokio::time::timeout_at(deadline, receiver.recv()) => match received {
+                Ok(Some(record)) => {
+                    batch.push(record);
+                    if batch.len() < config.max_batch_size {
+                        continue;
+                    }
+                    false
+                }
+                // every layer has been dropped
+                Ok(None) => true,
+                Err(_elapsed) => false,
+            },
+            _ = shutdown.requested.notified() => {
+                // the next records are rejected, the queued ones are exported
+                receiver.close();
+                while let Ok(record) = receiver.try_recv() {
+                    batch.push(record);
+                }
+                true
+            }
+        };
+        while !batch.is_empty() {
+            let rest = batch.split_off(batch.len().min(config.max_batch_size));
+            exporter.export(std::mem::replace(&mut batch, rest)).await;
+        }
+        if stop {
+            return;
+        }
+        deadline = tokio::time::Instant::now() + config.schedule_delay;
+    }
+}
+
+enum LogsClient {
+    Grpc {
+        client: LogsServiceClient<Channel>,
+        /// the grpc metadata of every export (from `OTEL_EXPORTER_OTLP_LOGS_HEADERS`)
+        metadata: MetadataMap,
+    },
+    Http {
+        client: reqwest::Client,
+        endpoint: String,
+        /// the headers of every export (from `OTEL_EXPORTER_OTLP_LOGS_HEADERS`)
+        headers: HashMap<String, String>,
+        compression: Compression,
+    },
+}
+
+impl LogsClient {
+    async fn export(&mut self, request: ExportLogsServiceRequest) -> Result<(), BoxError> {
+        match self {
+            LogsClient::Grpc { client, metadata } => {
+                let mut request = tonic::Request::new(request);
+                *request.metadata_mut() = metadata.clone();
+                client.export(request).await?;
+            }
+            LogsClient::Http {
+                client,
+                endpoint,
+                headers,
+                compression,
+            } => {
+                let (body, encoding) =
+                    compression.http_body(prost::Message::encode_to_vec(&request))?;
+                let mut builder = client
+                    .post(endpoint.as_str())
+                    .header(http::header::CONTENT_TYPE, "application/x-protobuf");
+                if let Some(encoding) = encoding {
+                    builder = builder.header(http::header::CONTENT_ENCODING, encoding);
+                }
+                for (key, value) in headers.iter() {
+                    builder = builder.header(key.as_str(), value.as_str());
+                }
+                builder.body(body).send().await?.error_for_status()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+struct LogsExporter {
+    client: LogsClient,
+    resource: ProtoResource,
+    timeout: Duration,
+}
+
+impl LogsExporter {
+    async fn export(&mut self, log_records: Vec<LogRecord>) {
+        let request = ExportLogsServiceRequest {
+            resource_logs: vec![ResourceLogs {
+                resource: Some(self.resource.clone()),
+                instrumentation_library_logs: vec![InstrumentationLibraryLogs {
+                    instrumentation_library: Some(InstrumentationLibrary {
+                        name: env!("CARGO_PKG_NAME").to_owned(),
+                        version: env!("CARGO_PKG_VERSION").to_owned(),
+                    }),
+                    log_records,
+                    schema_url: String::new(),
+                }],
+                schema_url: String::new(),
+            }],
+        };
+        // not exported (emitted from the exporter task), but still visible via the other layers
+        match tokio::time::timeout(self.timeout, self.client.export(request)).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(err)) => tracing::warn!("failed to export the logs: {err}"),
+            Err(_elapsed) => tracing::warn!(
+                "failed to export the logs: timeout after {:?}",
+                self.timeout
+            ),
+        }
+    }
+}
+
+fn is_excluded_target(target: &str) -> bool {
+    EXCLUDED_TARGETS.iter().any(|excluded| {
+        target
+            .strip_prefix(excluded)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+    })
+}
+
+fn severity_number(level: &Level) -> SeverityNumber {
+    match *level {
+        Level::TRACE => SeverityNumber::Trace,
+        Level::DEBUG => SeverityNumber::Debug,
+        Level::INFO => SeverityNumber::Info,
+        Level::WARN => SeverityNumber::Warn,
+        Level::ERROR => SeverityNumber::Error,
+    }
+}
+
+fn to_proto_resource(resource: &Resource) -> ProtoResource {
+    ProtoResource {
+        attributes: resource
+            .iter()
+            .map(|(key, value)| key_value(key.as_str(), value.clone()))
+            .collect(),
+        dropped_attributes_count: 0,
+    }
+}
+
+fn key_value(key: &str, value: opentelemetry::Value) -> KeyValue {
+    KeyValue {
+        key: key.to_owned(),
+        value: Some(AnyValue::from(value)),
+    }
+}
+
+fn to_nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64)
+}
+
+fn read_size_from_env(key: &str, default: usize) -> usize {
+    let size = std::env::var(key)
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .filter(|size| *size > 0)
+        .unwrap_or(default);
+    tracing::debug!(target: "otel::setup", "{key}={size}");
+    size
+}
+
+#[derive(Default)]
+struct LogRecordVisitor {
+    body: Option<AnyValue>,
+    attributes: Vec<KeyValue>,
+}
+
+impl LogRecordVisitor {
+    fn record(&mut self, field: &Field, value: opentelemetry::Value) {
+        match field.name() {
+            "message" => self.body = Some(AnyValue::from(value)),
+            // metadata of the events from `log` (already in the metadata of the event)
+            name if name.starts_with("log.") => {}
+            name => self.attributes.push(key_value(name, value)),
+        }
+    }
+}
+
+impl Visit for LogRecordVisitor {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.record(field, value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.record(field, value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        match i64::try_from(value) {
+            Ok(value) => self.record(field, value.into()),
+            Err(_) => self.record(field, value.to_string().into()),
+        }
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.record(field, value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record(field, value.to_owned().into());
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn std::error::Error + 'static)) {
+        self.record(field, value.to_string().into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.record(field, format!("{value:?}").into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert2::{check, let_assert};
+    use opentelemetry::trace::{TraceContextExt as _, TracerProvider as _};
+    use opentelemetry_proto::tonic::common::v1::any_value;
+    use rstest::*;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[rstest]
+    #[case(Level::TRACE, SeverityNumber::Trace)]
+    #[case(Level::DEBUG, SeverityNumber::Debug)]
+    #[case(Level::INFO, SeverityNumber::Info)]
+    #[case(Level::WARN, SeverityNumber::Warn)]
+    #[case(Level::ERROR, SeverityNumber::Error)]
+    fn test_severity_number(#[case] level: Level, #[case] expected: SeverityNumber) {
+        check!(severity_number(&level) == expected);
+    }
+
+    #[rstest]
+    #[case("hyper", true)]
+    #[case("hyper::proto::h1", true)]
+    #[case("tower::buffer", true)]
+    #[case("tower_http::trace", false)]
+    #[case("otel::tracing", false)]
+    fn test_is_excluded_target(#[case] target: &str, #[case] expected: bool) {
+        check!(is_excluded_target(target) == expected);
+    }
+
+    #[test]
+    fn record_event_with_trace_context() {
+        let (sender, mut receiver) = mpsc::channel(10);
+        let (shutdown, _stopped) = Shutdown::new();
+        // the provider should outlive the tracer (only weakly referenced by the tracer)
+        let provider = opentelemetry::sdk::trace::TracerProvider::builder().build();
+        let tracer = provider.tracer("test");
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(tracer.clone()))
+            .with(OtlpLogsLayer {
+                sender,
+                shutdown,
+                tracer: Some(tracer),
+            });
+
+        let span_context = tracing::subscriber::with_default(subscriber, || {
+            // a root span not sampled yet (nothing reads its context before the event)
+            let span = tracing::info_span!("request");
+            span.in_scope(|| tracing::warn!(user.id = 42, "hello {}", "world"));
+            span.context().span().span_context().clone()
+        });
+
+        let_assert!(Ok(record) = receiver.try_recv());
+        check!(record.severity_number == SeverityNumber::Warn as i32);
+        check!(record.severity_text == "WARN");
+        let_assert!(Some(any_value::Value::StringValue(body)) = record.body.and_then(|b| b.value));
+        check!(body == "hello world");
+        check!(record.attributes[0].key == "user.id");
+        check!(record.attributes.iter().any(|kv| kv.key == "code.lineno"));
+        check!(record.trace_id == span_context.trace_id().to_bytes().to_vec());
+        check!(record.span_id == span_context.span_id().to_bytes().to_vec());
+        check!(span_context.is_valid());
+        check!(record.flags == 1);
+    }
+
+    /// Spawn the exporter task on `runtime`, with an exporter never connected (nothing to export).
+    fn spawn_export_loop(
+        runtime: &tokio::runtime::Runtime,
+    ) -> (OtlpLogsLayer, tokio::task::JoinHandle<()>) {
+        let (sender, receiver) = mpsc::channel(10);
+        let (shutdown, stopped) = Shutdown::new();
+        let task = runtime.spawn(export_loop(
+            receiver,
+            shutdown.clone(),
+            stopped,
+            LogsExporter {
+                client: LogsClient::Grpc {
+                    client: LogsServiceClient::new(
+                        tonic::transport::Endpoint::from_static("http://127.0.0.1:4317") //Devskim: ignore DS137138
+                            .connect_lazy(),
+                    ),
+                    metadata: MetadataMap::new(),
+                },
+                resource: ProtoResource::default(),
+                timeout: Duration::from_secs(1),
+            },
+            BatchConfig {
+                schedule_delay: Duration::from_secs(3600),
+                max_batch_size: 10,
+            },
+        ));
+        let layer = OtlpLogsLayer {
+            sender,
+            shutdown,
+            tracer: None,
+        };
+        (layer, task)
+    }
+
+    #[test]
+    fn shutdown_blocking_stops_the_exporter_task() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (layer, task) = {
+            let _enter = runtime.enter();
+            spawn_export_loop(&runtime)
+        };
+
+        check!(layer.shutdown_blocking(Duration::from_secs(5)));
+        check!(runtime.block_on(task).is_ok());
+        // the queue is closed
+        check!(layer.sender.try_send(LogRecord::default()).is_err());
+    }
+
+    #[test]
+    fn shutdown_blocking_returns_when_the_runtime_is_dropped() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (layer, _task) = {
+            let _enter = runtime.enter();
+            spawn_export_loop(&runtime)
+        };
+        drop(runtime);
+
+        let start = std::time::Instant::now();
+        check!(layer.shutdown_blocking(Duration::from_secs(5)));
+        check!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn init_layer_with_the_default_env() {
+        // no test defines the OTLP variables
+        check!(std::env::vars().all(|(key, _)| !key.starts_with("OTEL_EXPORTER_OTLP")));
+        check!(init_layer(Resource::empty()).is_ok());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn export_with_the_headers_and_the_compression(
+        #[values("grpc", "http/protobuf")] protocol: &str,
+    ) {
+        use hyper::{service::service_fn, Body, HeaderMap, Request, Response};
+        use std::sync::{Arc, Mutex};
+
+        // a stand-in of a collector (grpc & http), recording the path & the headers of the requests
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap()); //Devskim: ignore DS137138
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let service = service_fn(move |req: Request<Body>| {
+                recorded
+                    .lock()
+                    .unwrap()
+                    .push((req.uri().path().to_owned(), req.headers().clone()));
+                let is_grpc = req.headers()["content-type"] == "application/grpc";
+                let (mut sender, body) = Body::channel();
+                tokio::spawn(async move {
+                    if !is_grpc {
+                        return Ok(());
+                    }
+                    // an empty `ExportLogsServiceResponse`
+                    sender.send_data(vec![0u8; 5].into()).await?;
+                    let mut trailers = HeaderMap::new();
+                    trailers.insert("grpc-status", "0".parse().unwrap());
+                    sender.send_trailers(trailers).await
+                });
+                let content_type = match is_grpc {
+                    true => "application/grpc",
+                    false => "application/x-protobuf",
+                };
+                let response = Response::builder()
+                    .header("content-type", content_type)
+                    .body(body);
+                async move { response }
+            });
+            hyper::server::conn::Http::new()
+                .serve_connection(stream, service)
+                .await
+        });
+
+        // gzip is rejected by `read_compression_from_env` without the feature `otlp_gzip`
+        let compression = if cfg!(feature = "otlp_gzip") {
+            Compression::Gzip
+        } else {
+            Compression::None
+        };
+        let headers = [("api-key".into(), "secret".into())].into();
+        let mut exporter = LogsExporter {
+            client: logs_client(
+                protocol,
+                endpoint,
+                headers,
+                Duration::from_secs(5),
+                compression,
+                None,
+            )
+            .unwrap(),
+            resource: ProtoResource::default(),
+            timeout: Duration::from_secs(5),
+        };
+        exporter.export(vec![LogRecord::default()]).await;
+
+        let requests = requests.lock().unwrap();
+        let_assert!([(path, headers)] = requests.as_slice());
+        check!(headers.get("api-key").unwrap() == "secret");
+        let expected_encoding = cfg!(feature = "otlp_gzip").then_some("gzip");
+        if protocol == "grpc" {
+            check!(path == "/opentelemetry.proto.collector.logs.v1.LogsService/Export");
+            check!(headers.get("grpc-encoding").map(|v| v.to_str().unwrap()) == expected_encoding);
+        } else {
+            check!(path == "/v1/logs");
+            check!(
+                headers.get("content-encoding").map(|v| v.to_str().unwrap()) == expected_encoding
+            );
+        }
+    }
+}
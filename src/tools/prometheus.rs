@@ -0,0 +1,100 @@
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use opentelemetry::metrics::MetricsError;
+use opentelemetry::sdk::export::metrics::aggregation;
+use opentelemetry::sdk::metrics::{controllers, processors, selectors};
+use opentelemetry::sdk::Resource;
+use opentelemetry_prometheus::PrometheusExporter;
+use prometheus::{Encoder, TextEncoder};
+
+/// Install a meter provider (as global meter provider) collected by the returned
+/// prometheus exporter, use [`metrics_router`] to serve the metrics.
+///
+/// Should be called before the creation of the instruments
+/// (eg before `opentelemetry_metrics_layer()`).
+pub fn init_meter(resource: Resource) -> Result<PrometheusExporter, MetricsError> {
+    let controller = controllers::basic(
+        processors::factory(
+            selectors::simple::histogram(super::HISTOGRAM_BOUNDARIES),
+            aggregation::cumulative_temporality_selector(),
+        )
+        .with_memory(true),
+    )
+    .with_resource(resource)
+    .build();
+    opentelemetry_prometheus::exporter(controller).try_init()
+}
+
+/// A router serving the metrics of `exporter` in the Prometheus text format on `GET /metrics`.
+///
+/// ```
+/// # fn build(app: axum::Router) -> Result<axum::Router, Box<dyn std::error::Error>> {
+/// use axum_tracing_opentelemetry::{opentelemetry_metrics_layer, prometheus};
+///
+/// // or `DetectResource::default().build()` (feature `tracer`) to share the resource of the tracer
+/// let exporter = prometheus::init_meter(opentelemetry::sdk::Resource::default())?;
+/// let app = app
+///     .layer(opentelemetry_metrics_layer())
+///     // not measured by the metrics layer
+///     .merge(prometheus::metrics_router(exporter));
+/// # Ok(app)
+/// # }
+/// ```
+pub fn metrics_router<S>(exporter: PrometheusExporter) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    Router::new()
+        .route("/metrics", get(metrics))
+        .with_state(exporter)
+}
+
+async fn metrics(State(exporter): State<PrometheusExporter>) -> Response {
+    let encoder = TextEncoder::new();
+    let mut buffer = vec![];
+    match encoder.encode(&exporter.registry().gather(), &mut buffer) {
+        Ok(()) => (
+            [(header::CONTENT_TYPE, encoder.format_type().to_owned())],
+            buffer,
+        )
+            .into_response(),
+        Err(err) => {
+            tracing::warn!("failed to encode the metrics: {err}");
+            (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opentelemetry_metrics_layer;
+    use assert2::{check, let_assert};
+    use axum::{body::Body, http::Request};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn serve_metrics_of_the_metrics_layer() {
+        let exporter = init_meter(Resource::empty()).unwrap();
+        let app = Router::new()
+            .route("/users/:id", get(|| async { StatusCode::OK }))
+            .layer(opentelemetry_metrics_layer())
+            .merge(metrics_router(exporter));
+        let req = Request::get("/users/123").body(Body::empty()).unwrap();
+        app.clone().oneshot(req).await.unwrap();
+
+        let req = Request::get("/metrics").body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        check!(res.status() == StatusCode::OK);
+        check!(res.headers()[header::CONTENT_TYPE] == "text/plain; version=0.0.4");
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let_assert!(Ok(body) = std::str::from_utf8(&body));
+        check!(body.contains(r#"http_server_duration_count{http_flavor="1.1",http_method="GET",http_route="/users/:id",http_scheme="HTTP",http_status_code="200"} 1"#));
+        check!(body.contains("http_server_active_requests"));
+    }
+}
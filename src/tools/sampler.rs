@@ -0,0 +1,494 @@
+//! The sampler of the traces, shared by the pipelines (`otlp`, `jaeger`, `zipkin`, `stdio`).
+
+use opentelemetry::sdk::trace::Sampler;
+use opentelemetry::sdk::Resource;
+use opentelemetry::trace::TraceError;
+#[cfg(feature = "jaeger_remote_sampler")]
+use opentelemetry_semantic_conventions as semcov;
+
+/// Read the sampler from `OTEL_TRACES_SAMPLER` & `OTEL_TRACES_SAMPLER_ARG`.
+///
+/// An invalid configuration (unknown sampler, ratio not in `0..=1`,...) is returned as an error
+/// when `strict`, else it is reported as a warning and the default sampler
+/// `parentbased_always_on` is used (like by the `init_tracer` of the pipelines).
+///
+/// ```
+/// use axum_tracing_opentelemetry::sampler::read_sampler_from_env;
+/// use opentelemetry::sdk::{trace, Resource};
+///
+/// # fn main() -> Result<(), opentelemetry::trace::TraceError> {
+/// let resource = Resource::empty();
+/// // fail on an invalid configuration of the sampler
+/// let sampler = read_sampler_from_env(&resource, true)?;
+/// let config = trace::config().with_resource(resource).with_sampler(sampler);
+/// # let _ = config;
+/// # Ok(())
+/// # }
+/// ```
+pub fn read_sampler_from_env(resource: &Resource, strict: bool) -> Result<Sampler, TraceError> {
+    sampler_from_config(resource, SamplerConfig::from_env(), strict)
+}
+
+/// Build the sampler from its configuration, like [`read_sampler_from_env`].
+pub(crate) fn sampler_from_config(
+    resource: &Resource,
+    config: Result<SamplerConfig, SamplerConfigError>,
+    strict: bool,
+) -> Result<Sampler, TraceError> {
+    match config
+        .map_err(TraceError::from)
+        .and_then(|config| config.build(resource))
+    {
+        Err(err) if !strict => {
+            tracing::warn!(target: "otel::setup", "invalid configuration of the sampler (fallback to parentbased_always_on): {err}");
+            SamplerConfig::default().build(&Resource::empty())
+        }
+        sampler => sampler,
+    }
+}
+
+/// The sampler of the traces, as configured by the environment variables `OTEL_TRACES_SAMPLER`
+/// & `OTEL_TRACES_SAMPLER_ARG`.
+///
+/// see <https://opentelemetry.io/docs/reference/specification/sdk-environment-variables/#general-sdk-configuration>
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum SamplerConfig {
+    AlwaysOn,
+    AlwaysOff,
+    TraceIdRatio(f64),
+    #[default]
+    ParentBasedAlwaysOn,
+    ParentBasedAlwaysOff,
+    ParentBasedTraceIdRatio(f64),
+    /// with the `OTEL_TRACES_SAMPLER_ARG` (feature `jaeger_remote_sampler`)
+    JaegerRemote(String),
+    /// with the `OTEL_TRACES_SAMPLER_ARG` (feature `xray`)
+    Xray(String),
+}
+
+impl SamplerConfig {
+    /// Read the configuration from `OTEL_TRACES_SAMPLER` & `OTEL_TRACES_SAMPLER_ARG`
+    /// (default: `parentbased_always_on`).
+    pub fn from_env() -> Result<Self, SamplerConfigError> {
+        let name = std::env::var("OTEL_TRACES_SAMPLER").unwrap_or_default();
+        tracing::debug!(target: "otel::setup", OTEL_TRACES_SAMPLER = ?name);
+        let arg = std::env::var("OTEL_TRACES_SAMPLER_ARG").ok();
+        tracing::debug!(target: "otel::setup", OTEL_TRACES_SAMPLER_ARG = arg);
+        Self::parse(&name, arg.as_deref())
+    }
+
+    /// Parse the name of the sampler and its argument (the ratio of `traceidratio` &
+    /// `parentbased_traceidratio`, in `0..=1`, default: 1).
+    pub fn parse(name: &str, arg: Option<&str>) -> Result<Self, SamplerConfigError> {
+        let arg = arg.map(str::trim).filter(|v| !v.is_empty());
+        match name.trim().to_lowercase().as_str() {
+            "" | "parentbased_always_on" => Ok(SamplerConfig::ParentBasedAlwaysOn),
+            "always_on" => Ok(SamplerConfig::AlwaysOn),
+            "always_off" => Ok(SamplerConfig::AlwaysOff),
+            "traceidratio" => parse_ratio(arg).map(SamplerConfig::TraceIdRatio),
+            "parentbased_always_off" => Ok(SamplerConfig::ParentBasedAlwaysOff),
+            "parentbased_traceidratio" => {
+                parse_ratio(arg).map(SamplerConfig::ParentBasedTraceIdRatio)
+            }
+            #[cfg(feature = "jaeger_remote_sampler")]
+            "jaeger_remote" => Ok(SamplerConfig::JaegerRemote(
+                arg.unwrap_or_default().to_string(),
+            )),
+            #[cfg(not(feature = "jaeger_remote_sampler"))]
+            "jaeger_remote" => Err(SamplerConfigError::MissingFeature {
+                sampler: "jaeger_remote".to_string(),
+                feature: "jaeger_remote_sampler",
+            }),
+            #[cfg(feature = "xray")]
+            "xray" => Ok(SamplerConfig::Xray(arg.unwrap_or_default().to_string())),
+            #[cfg(not(feature = "xray"))]
+            "xray" => Err(SamplerConfigError::MissingFeature {
+                sampler: "xray".to_string(),
+                feature: "xray",
+            }),
+            _ => Err(SamplerConfigError::UnknownSampler(name.to_string())),
+        }
+    }
+
+    /// Create the sampler, the remote samplers (`jaeger_remote` & `xray`) use the
+    /// `service.name` of `resource` and start to poll their endpoint (on the tokio runtime).
+    #[cfg_attr(
+        not(any(feature = "jaeger_remote_sampler", feature = "xray")),
+        allow(unused_variables)
+    )]
+    pub fn build(&self, resource: &Resource) -> Result<Sampler, TraceError> {
+        Ok(match self {
+            SamplerConfig::AlwaysOn => Sampler::AlwaysOn,
+            SamplerConfig::AlwaysOff => Sampler::AlwaysOff,
+            SamplerConfig::TraceIdRatio(ratio) => Sampler::TraceIdRatioBased(*ratio),
+            SamplerConfig::ParentBasedAlwaysOn => Sampler::ParentBased(Box::new(Sampler::AlwaysOn)),
+            SamplerConfig::ParentBasedAlwaysOff => {
+                Sampler::ParentBased(Box::new(Sampler::AlwaysOff))
+            }
+            SamplerConfig::ParentBasedTraceIdRatio(ratio) => {
+                Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(*ratio)))
+            }
+            #[cfg(feature = "jaeger_remote_sampler")]
+            SamplerConfig::JaegerRemote(arg) => {
+                let service_name = resource
+                    .get(semcov::resource::SERVICE_NAME)
+                    .map(|v| v.to_string())
+                    .unwrap_or_else(|| "unknown_service".to_string());
+                jaeger_remote_sampler(arg, service_name)?
+            }
+            #[cfg(feature = "xray")]
+            SamplerConfig::Xray(arg) => super::xray::sampler_from_arg(arg, resource)?,
+            #[allow(unreachable_patterns)]
+            SamplerConfig::JaegerRemote(_) | SamplerConfig::Xray(_) => {
+                return Err(TraceError::from(format!(
+                    "unsupported sampler {self:?}, enable the required compile feature"
+                )))
+            }
+        })
+    }
+}
+
+fn parse_ratio(arg: Option<&str>) -> Result<f64, SamplerConfigError> {
+    let Some(arg) = arg else {
+        return Ok(1.0);
+    };
+    let ratio = arg
+        .parse::<f64>()
+        .map_err(|_| SamplerConfigError::InvalidRatio(arg.to_string()))?;
+    if (0.0..=1.0).contains(&ratio) {
+        Ok(ratio)
+    } else {
+        Err(SamplerConfigError::RatioOutOfRange(ratio))
+    }
+}
+
+/// An invalid configuration of the sampler, see [`SamplerConfig`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum SamplerConfigError {
+    /// `OTEL_TRACES_SAMPLER` is not a known sampler
+    UnknownSampler(String),
+    /// the sampler requires a compile feature
+    MissingFeature {
+        sampler: String,
+        feature: &'static str,
+    },
+    /// `OTEL_TRACES_SAMPLER_ARG` is not a number
+    InvalidRatio(String),
+    /// `OTEL_TRACES_SAMPLER_ARG` is not in `0..=1`
+    RatioOutOfRange(f64),
+}
+
+impl std::fmt::Display for SamplerConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SamplerConfigError::UnknownSampler(name) => {
+                write!(f, "unknown sampler from env OTEL_TRACES_SAMPLER: '{name}'")
+            }
+            SamplerConfigError::MissingFeature { sampler, feature } => write!(
+                f,
+                "unsupported sampler from env OTEL_TRACES_SAMPLER: '{sampler}', try to enable compile feature '{feature}'"
+            ),
+            SamplerConfigError::InvalidRatio(arg) => {
+                write!(f, "invalid ratio from env OTEL_TRACES_SAMPLER_ARG: '{arg}'")
+            }
+            SamplerConfigError::RatioOutOfRange(ratio) => write!(
+                f,
+                "ratio from env OTEL_TRACES_SAMPLER_ARG not in 0..=1: '{ratio}'"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SamplerConfigError {}
+
+impl From<SamplerConfigError> for TraceError {
+    fn from(err: SamplerConfigError) -> Self {
+        TraceError::Other(Box::new(err))
+    }
+}
+
+/// Create a sampler driven by the sampling strategies served by a jaeger agent (or any
+/// service implementing the jaeger remote sampling protocol), polled in background.
+///
+/// `arg` is a comma-separated list of `key=value` (the format of `OTEL_TRACES_SAMPLER_ARG`):
+///
+/// - `endpoint`: the url of the sampling strategies (default: `http://localhost:5778/sampling`)
+/// - `pollingIntervalMs`: the interval between two polls, in milliseconds (default: 60000)
+/// - `initialSamplingRate`: the ratio (in `0..=1`) of the fallback sampler, used until a
+///   strategy is received (eg while the endpoint is unreachable) (default: 0.001)
+///
+/// The probabilistic, rate limiting and per operation strategies are supported.
+/// Limitation: the `strategyType` of a rate limiting strategy is only understood when
+/// serialized as `RATELIMITING` (not as `RATE_LIMITING`), by the sampler of `opentelemetry_sdk`.
+#[cfg(feature = "jaeger_remote_sampler")]
+fn jaeger_remote_sampler(arg: &str, service_name: String) -> Result<Sampler, TraceError> {
+    let mut endpoint = "http://localhost:5778/sampling".to_string(); //Devskim: ignore DS137138
+    let mut polling_interval = std::time::Duration::from_millis(60_000);
+    let mut initial_sampling_rate = 0.001;
+    for (key, value) in arg
+        .split(',')
+        .filter_map(|kv| kv.split_once('='))
+        .map(|(k, v)| (k.trim(), v.trim()))
+    {
+        match key {
+            "endpoint" => endpoint = value.to_string(),
+            "pollingIntervalMs" => {
+                polling_interval = value
+                    .parse()
+                    .map(std::time::Duration::from_millis)
+                    .map_err(|err| {
+                        TraceError::from(format!("invalid pollingIntervalMs '{value}': {err}"))
+                    })?
+            }
+            "initialSamplingRate" => initial_sampling_rate = parse_ratio(Some(value))?,
+            _ => {
+                tracing::warn!(target: "otel::setup", "unknown key '{key}' in OTEL_TRACES_SAMPLER_ARG of jaeger_remote")
+            }
+        }
+    }
+    // an async client, a blocking client can not be used from the tokio runtime
+    Sampler::jaeger_remote(
+        opentelemetry::runtime::Tokio,
+        reqwest::Client::new(),
+        Sampler::TraceIdRatioBased(initial_sampling_rate),
+        service_name,
+    )
+    .with_endpoint(endpoint)
+    .with_update_interval(polling_interval)
+    .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert2::assert;
+    use rstest::*;
+
+    #[rstest]
+    #[case("", None, SamplerConfig::ParentBasedAlwaysOn)]
+    #[case("always_on", None, SamplerConfig::AlwaysOn)]
+    #[case(" Always_Off ", None, SamplerConfig::AlwaysOff)]
+    #[case("traceidratio", None, SamplerConfig::TraceIdRatio(1.0))]
+    #[case("traceidratio", Some(""), SamplerConfig::TraceIdRatio(1.0))]
+    #[case("traceidratio", Some("0.25"), SamplerConfig::TraceIdRatio(0.25))]
+    #[case("traceidratio", Some("0"), SamplerConfig::TraceIdRatio(0.0))]
+    #[case(
+        "parentbased_always_on",
+        Some("0.5"),
+        SamplerConfig::ParentBasedAlwaysOn
+    )]
+    #[case("parentbased_always_off", None, SamplerConfig::ParentBasedAlwaysOff)]
+    #[case(
+        "parentbased_traceidratio",
+        Some(" 0.5 "),
+        SamplerConfig::ParentBasedTraceIdRatio(0.5)
+    )]
+    fn test_sampler_config_parse(
+        #[case] name: &str,
+        #[case] arg: Option<&str>,
+        #[case] expected: SamplerConfig,
+    ) {
+        assert!(SamplerConfig::parse(name, arg) == Ok(expected));
+    }
+
+    #[rstest]
+    #[case(
+        "always",
+        None,
+        Err(SamplerConfigError::UnknownSampler("always".to_string()))
+    )]
+    #[case(
+        "traceidratio",
+        Some("half"),
+        Err(SamplerConfigError::InvalidRatio("half".to_string()))
+    )]
+    #[case(
+        "traceidratio",
+        Some("1.5"),
+        Err(SamplerConfigError::RatioOutOfRange(1.5))
+    )]
+    #[case(
+        "parentbased_traceidratio",
+        Some("-0.1"),
+        Err(SamplerConfigError::RatioOutOfRange(-0.1))
+    )]
+    fn test_sampler_config_parse_failed(
+        #[case] name: &str,
+        #[case] arg: Option<&str>,
+        #[case] expected: Result<SamplerConfig, SamplerConfigError>,
+    ) {
+        let actual = SamplerConfig::parse(name, arg);
+        assert!(actual.is_err());
+        assert!(actual == expected);
+    }
+
+    #[test]
+    fn sampler_config_error_message() {
+        let err = SamplerConfig::parse("always", None).unwrap_err();
+        assert!(err.to_string() == "unknown sampler from env OTEL_TRACES_SAMPLER: 'always'");
+        let err = SamplerConfig::parse("traceidratio", Some("2")).unwrap_err();
+        assert!(err.to_string() == "ratio from env OTEL_TRACES_SAMPLER_ARG not in 0..=1: '2'");
+    }
+
+    #[cfg(not(feature = "xray"))]
+    #[test]
+    fn sampler_config_failed_on_missing_feature() {
+        let err = SamplerConfig::parse("xray", None).unwrap_err();
+        assert!(
+            err.to_string()
+                == "unsupported sampler from env OTEL_TRACES_SAMPLER: 'xray', try to enable compile feature 'xray'"
+        );
+    }
+
+    #[rstest]
+    #[case(false, true)]
+    #[case(true, false)]
+    fn invalid_sampler_fallback_unless_strict(#[case] strict: bool, #[case] expected_ok: bool) {
+        let config = SamplerConfig::parse("traceidratio", Some("2"));
+        assert!(sampler_from_config(&Resource::empty(), config, strict).is_ok() == expected_ok);
+    }
+
+    #[cfg(feature = "jaeger_remote_sampler")]
+    fn is_sampled(sampler: &Sampler, trace_id: u128, name: &str) -> bool {
+        use opentelemetry::sdk::trace::ShouldSample;
+        use opentelemetry::trace::{SamplingDecision, SpanKind, TraceId};
+
+        let result = sampler.should_sample(
+            None,
+            TraceId::from_bytes(trace_id.to_be_bytes()),
+            name,
+            &SpanKind::Server,
+            &Default::default(),
+            &[],
+            &Default::default(),
+        );
+        result.decision == SamplingDecision::RecordAndSample
+    }
+
+    /// Poll `sampler` until the decision for the span `name` matches `expected` (the strategy
+    /// is fetched in background).
+    #[cfg(feature = "jaeger_remote_sampler")]
+    async fn wait_decision(sampler: &Sampler, name: &str, expected: bool) -> bool {
+        for i in 0..500 {
+            if is_sampled(sampler, i + 1, name) == expected {
+                return true;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        false
+    }
+
+    #[cfg(feature = "jaeger_remote_sampler")]
+    #[rstest]
+    #[case("initialSamplingRate=1", true)]
+    #[case("initialSamplingRate=0", false)]
+    #[tokio::test]
+    async fn jaeger_remote_sampler_fallback_while_unreachable(
+        #[case] arg: &str,
+        #[case] expected: bool,
+    ) {
+        // a free port, nothing listen on it
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let arg = format!("endpoint=http://{addr}/sampling,pollingIntervalMs=10,{arg}"); //Devskim: ignore DS137138
+        let sampler = jaeger_remote_sampler(&arg, "test".to_string()).unwrap();
+        // let the sampler poll (and fail) a few times
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        for i in 1..=10 {
+            assert!(is_sampled(&sampler, i, "hello") == expected);
+        }
+    }
+
+    #[cfg(feature = "jaeger_remote_sampler")]
+    #[rstest]
+    #[case(
+        r#"{"strategyType":"PROBABILISTIC","probabilisticSampling":{"samplingRate":1.0}}"#,
+        "hello",
+        true
+    )]
+    #[case(
+        r#"{"strategyType":"PROBABILISTIC","operationSampling":{"defaultSamplingProbability":0.0,"defaultLowerBoundTracesPerSecond":0.0,"defaultUpperBoundTracesPerSecond":0.0,"perOperationStrategies":[{"operation":"hello","probabilisticSampling":{"samplingRate":1.0}}]}}"#,
+        "hello",
+        true
+    )]
+    #[case(
+        r#"{"strategyType":"PROBABILISTIC","operationSampling":{"defaultSamplingProbability":0.0,"defaultLowerBoundTracesPerSecond":0.0,"defaultUpperBoundTracesPerSecond":0.0,"perOperationStrategies":[{"operation":"hello","probabilisticSampling":{"samplingRate":1.0}}]}}"#,
+        "world",
+        false
+    )]
+    #[tokio::test]
+    async fn jaeger_remote_sampler_apply_the_served_strategy(
+        #[case] strategy: &'static str,
+        #[case] name: &str,
+        #[case] expected: bool,
+    ) {
+        use axum::{extract::Query, http::StatusCode, routing::get, Router};
+        use std::collections::HashMap;
+
+        // a stand-in of the jaeger agent, serving the strategy of the service `test`
+        let agent = Router::new().route(
+            "/sampling",
+            get(
+                move |Query(params): Query<HashMap<String, String>>| async move {
+                    match params.get("service").map(String::as_str) {
+                        Some("test") => (StatusCode::OK, strategy),
+                        _ => (StatusCode::NOT_FOUND, ""),
+                    }
+                },
+            ),
+        );
+        let server =
+            axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(agent.into_make_service());
+        let arg = format!(
+            "endpoint=http://{}/sampling,pollingIntervalMs=10,initialSamplingRate={}", //Devskim: ignore DS137138
+            server.local_addr(),
+            if expected { 0 } else { 1 }
+        );
+        tokio::spawn(server);
+
+        let sampler = jaeger_remote_sampler(&arg, "test".to_string()).unwrap();
+        assert!(wait_decision(&sampler, name, expected).await);
+    }
+
+    #[cfg(feature = "jaeger_remote_sampler")]
+    #[tokio::test]
+    async fn jaeger_remote_sampler_limit_the_rate() {
+        use axum::{routing::get, Router};
+
+        // `RATELIMITING`, see the limitation of `jaeger_remote_sampler`
+        let agent = Router::new().route(
+            "/sampling",
+            get(|| async {
+                r#"{"strategyType":"RATELIMITING","rateLimitingSampling":{"maxTracesPerSecond":1}}"#
+            }),
+        );
+        let server =
+            axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(agent.into_make_service());
+        let arg = format!(
+            "endpoint=http://{}/sampling,pollingIntervalMs=60000,initialSamplingRate=0", //Devskim: ignore DS137138
+            server.local_addr(),
+        );
+        tokio::spawn(server);
+
+        let sampler = jaeger_remote_sampler(&arg, "test".to_string()).unwrap();
+        assert!(wait_decision(&sampler, "hello", true).await);
+        // the burst is bounded by the size of the leaky bucket (100)
+        let sampled = (1..=1000)
+            .filter(|i| is_sampled(&sampler, *i, "hello"))
+            .count();
+        assert!(sampled < 200);
+    }
+
+    #[cfg(feature = "jaeger_remote_sampler")]
+    #[rstest]
+    #[case("pollingIntervalMs=often")]
+    #[case("initialSamplingRate=half")]
+    #[case("initialSamplingRate=1.5")]
+    #[case("initialSamplingRate=-1")]
+    #[tokio::test]
+    async fn jaeger_remote_sampler_failed_on_invalid_arg(#[case] arg: &str) {
+        assert!(jaeger_remote_sampler(arg, "test".to_string()).is_err());
+    }
+}
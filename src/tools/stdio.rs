@@ -6,6 +6,8 @@ use opentelemetry::{
 use std::fmt::Debug;
 use std::io::Write;
 
+use super::sampler::read_sampler_from_env;
+
 pub fn identity<W: Write>(v: PipelineBuilder<W>) -> PipelineBuilder<W> {
     v
 }
@@ -15,17 +17,30 @@ pub fn init_tracer<F, W>(
     transform: F,
     w: W,
 ) -> Result<sdktrace::Tracer, TraceError>
+where
+    F: FnOnce(PipelineBuilder<W>) -> PipelineBuilder<W>,
+    W: Write + Debug + Send + 'static,
+{
+    let sampler = read_sampler_from_env(&resource, false)?;
+    init_tracer_with_sampler(resource, transform, w, sampler)
+}
+
+/// Like [`init_tracer`], with the `sampler` instead of the one from `OTEL_TRACES_SAMPLER`.
+pub fn init_tracer_with_sampler<F, W>(
+    resource: Resource,
+    transform: F,
+    w: W,
+    sampler: sdktrace::Sampler,
+) -> Result<sdktrace::Tracer, TraceError>
 where
     F: FnOnce(PipelineBuilder<W>) -> PipelineBuilder<W>,
     W: Write + Debug + Send + 'static,
 {
     global::set_text_map_propagator(TraceContextPropagator::new());
 
-    let mut pipeline = PipelineBuilder::default().with_writer(w).with_trace_config(
-        sdktrace::config()
-            .with_resource(resource)
-            .with_sampler(sdktrace::Sampler::AlwaysOn),
-    );
+    let mut pipeline = PipelineBuilder::default()
+        .with_writer(w)
+        .with_trace_config(super::trace_config(resource, sampler));
     pipeline = transform(pipeline);
     Ok(pipeline.install_simple())
 }
@@ -42,3 +57,29 @@ impl Write for WriteNoWhere {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::SamplerConfig;
+    use assert2::{check, let_assert};
+
+    #[test]
+    fn install_the_trace_config() {
+        let resource = Resource::new([opentelemetry::KeyValue::new(
+            "deployment.environment",
+            "test",
+        )]);
+        let sampler = SamplerConfig::TraceIdRatio(0.25).build(&resource).unwrap();
+        let tracer = init_tracer_with_sampler(resource, identity, WriteNoWhere, sampler).unwrap();
+        let_assert!(Some(provider) = tracer.provider());
+        check!(format!("{:?}", provider.config().sampler) == "TraceIdRatioBased(0.25)");
+        check!(
+            provider
+                .config()
+                .resource
+                .get("deployment.environment".into())
+                == Some("test".into())
+        );
+    }
+}
@@ -1,53 +1,226 @@
+use crate::resource::DetectResource;
 use axum::BoxError;
 use opentelemetry::sdk::trace::Tracer;
-use tracing::{info, Subscriber};
-use tracing_opentelemetry::OpenTelemetryLayer;
+use opentelemetry::sdk::Resource;
+use opentelemetry::trace::{SamplingDecision, SpanId, TraceContextExt, TraceFlags, TraceId};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::field::{Field, Visit};
+use tracing::{info, Event, Subscriber};
+use tracing_opentelemetry::{OpenTelemetryLayer, OtelData, PreSampledTracer};
 use tracing_subscriber::filter::EnvFilter;
-use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::fmt::format::{FmtSpan, Format, Json, Writer};
+use tracing_subscriber::fmt::time::{FormatTime, SystemTime};
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
 use tracing_subscriber::layer::SubscriberExt;
-use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::registry::{LookupSpan, SpanRef};
 use tracing_subscriber::Layer;
 
+/// The format of the logs printed by [`build_logger_text`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// One json object by event, with the trace context (see [`JsonWithTraceContext`])
+    Json,
+    /// Multi-lines human readable events (see `tracing_subscriber::fmt::format::Pretty`)
+    Pretty,
+    /// One line by event, with the fields of the spans
+    Compact,
+    /// One line of `key=value` by event, with the trace context (see [`Logfmt`])
+    Logfmt,
+}
+
+impl LogFormat {
+    /// Read `LOG_FORMAT` (`json`, `pretty`, `compact` or `logfmt`), fallback to the default
+    /// (`pretty` for debug build, `json` else) when unset or not supported.
+    pub fn from_env() -> Self {
+        let format = std::env::var("LOG_FORMAT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_default();
+        tracing::debug!(target: "otel::setup", LOG_FORMAT = ?format);
+        format
+    }
+}
+
+impl Default for LogFormat {
+    fn default() -> Self {
+        if cfg!(debug_assertions) {
+            LogFormat::Pretty
+        } else {
+            LogFormat::Json
+        }
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "json" => Ok(LogFormat::Json),
+            "pretty" => Ok(LogFormat::Pretty),
+            "compact" => Ok(LogFormat::Compact),
+            "logfmt" => Ok(LogFormat::Logfmt),
+            other => Err(format!(
+                "unsupported log format '{other}' (supported: json, pretty, compact, logfmt)"
+            )),
+        }
+    }
+}
+
+/// The layer printing the logs on stdout, in the format from `LOG_FORMAT` (see [`LogFormat`]).
 pub fn build_logger_text<S>() -> Box<dyn Layer<S> + Send + Sync + 'static>
 where
     S: Subscriber + for<'a> LookupSpan<'a>,
 {
-    if cfg!(debug_assertions) {
-        Box::new(
+    logger_text(LogFormat::from_env())
+}
+
+fn logger_text<S>(format: LogFormat) -> Box<dyn Layer<S> + Send + Sync + 'static>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    match format {
+        LogFormat::Pretty => Box::new(
             tracing_subscriber::fmt::layer()
                 .pretty()
                 .with_line_number(true)
                 .with_thread_names(true)
                 .with_span_events(FmtSpan::NEW | FmtSpan::CLOSE)
                 .with_timer(tracing_subscriber::fmt::time::uptime()),
-        )
-    } else {
-        Box::new(
+        ),
+        LogFormat::Compact => Box::new(
             tracing_subscriber::fmt::layer()
-                .json()
+                .compact()
                 .with_span_events(FmtSpan::NEW | FmtSpan::CLOSE)
                 .with_timer(tracing_subscriber::fmt::time::uptime()),
+        ),
+        LogFormat::Json => Box::new(
+            tracing_subscriber::fmt::layer()
+                .json()
+                .with_span_events(FmtSpan::NEW | FmtSpan::CLOSE)
+                .event_format(JsonWithTraceContext::new(
+                    tracing_subscriber::fmt::format()
+                        .json()
+                        .with_timer(tracing_subscriber::fmt::time::uptime()),
+                )),
+        ),
+        LogFormat::Logfmt => Box::new(
+            tracing_subscriber::fmt::layer()
+                .with_span_events(FmtSpan::NEW | FmtSpan::CLOSE)
+                .event_format(Logfmt::new().with_timer(tracing_subscriber::fmt::time::uptime())),
+        ),
+    }
+}
+
+/// Json format of the events, with the top-level fields `trace_id`, `span_id` & `trace_flags`
+/// of the otel span of the current span (when there is one), to jump from the logs to the trace.
+///
+/// ```
+/// use axum_tracing_opentelemetry::tracing_subscriber_ext::JsonWithTraceContext;
+///
+/// let layer = tracing_subscriber::fmt::layer::<tracing_subscriber::Registry>()
+///     .json()
+///     .event_format(JsonWithTraceContext::new(tracing_subscriber::fmt::format().json()));
+/// ```
+#[derive(Debug, Clone)]
+pub struct JsonWithTraceContext<T> {
+    inner: Format<Json, T>,
+    tracer: Option<Tracer>,
+}
+
+impl<T> JsonWithTraceContext<T> {
+    /// Add the trace context to the events formatted by `inner`.
+    pub fn new(inner: Format<Json, T>) -> Self {
+        JsonWithTraceContext {
+            inner,
+            tracer: None,
+        }
+    }
+
+    /// The tracer of the `OpenTelemetryLayer`, to sample the spans not sampled yet
+    /// (default: the tracer installed by [`init_subscribers`]).
+    pub fn with_tracer(mut self, tracer: Tracer) -> Self {
+        self.tracer = Some(tracer);
+        self
+    }
+}
+
+impl<S, N, T> FormatEvent<S, N> for JsonWithTraceContext<T>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+    T: FormatTime,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let Some((trace_id, span_id, trace_flags)) = ctx
+            .parent_span()
+            .and_then(|span| find_trace_context(&span, self.tracer.as_ref()))
+        else {
+            return self.inner.format_event(ctx, writer, event);
+        };
+        let mut line = String::new();
+        self.inner
+            .format_event(ctx, Writer::new(&mut line), event)?;
+        let Some(rest) = line.strip_prefix('{') else {
+            return writer.write_str(&line);
+        };
+        let separator = if rest.starts_with('}') { "" } else { "," };
+        write!(
+            writer,
+            r#"{{"trace_id":"{trace_id}","span_id":"{span_id}","trace_flags":"{:02x}"{separator}{rest}"#,
+            trace_flags.to_u8()
         )
     }
 }
 
+/// The filter of the logs & traces from `RUST_LOG` (fallback to `OTEL_LOG_LEVEL`, then `info`),
+/// with `axum_tracing_opentelemetry=info,otel=debug` appended.
+///
+/// ```
+/// use tracing_subscriber::layer::SubscriberExt;
+///
+/// let subscriber = tracing_subscriber::registry()
+///     .with(axum_tracing_opentelemetry::tracing_subscriber_ext::build_loglevel_filter_layer());
+/// # let _ = subscriber;
+/// ```
 pub fn build_loglevel_filter_layer() -> tracing_subscriber::filter::EnvFilter {
     // filter what is output on log (fmt)
-    // std::env::set_var("RUST_LOG", "warn,axum_tracing_opentelemetry=info,otel=debug");
-    std::env::set_var(
-        "RUST_LOG",
-        format!(
-            // `axum_tracing_opentelemetry` should be a level info to emit opentelemetry trace & span
-            // `otel::setup` set to debug to log detected resources, configuration read and infered
-            "{},axum_tracing_opentelemetry=info,otel=debug",
-            std::env::var("RUST_LOG")
-                .or_else(|_| std::env::var("OTEL_LOG_LEVEL"))
-                .unwrap_or_else(|_| "info".to_string())
-        ),
+    // eg "warn,axum_tracing_opentelemetry=info,otel=debug"
+    let directives = format!(
+        // `axum_tracing_opentelemetry` should be a level info to emit opentelemetry trace & span
+        // `otel::setup` set to debug to log detected resources, configuration read and infered
+        "{},axum_tracing_opentelemetry=info,otel=debug",
+        std::env::var("RUST_LOG")
+            .or_else(|_| std::env::var("OTEL_LOG_LEVEL"))
+            .unwrap_or_else(|_| "info".to_string())
     );
-    EnvFilter::from_default_env()
+    EnvFilter::builder().parse_lossy(directives)
 }
 
+/// The `OpenTelemetryLayer` with the OTLP tracer (see [`otlp::init_tracer`](crate::otlp::init_tracer))
+/// and the resource detected from the environment, the propagator is installed from
+/// `OTEL_PROPAGATORS` (see [`init_propagator`](crate::init_propagator)).
+///
+/// Should be called from a tokio runtime (the spans are exported by batch from a spawned task).
+///
+/// ```no_run
+/// use tracing_subscriber::layer::SubscriberExt;
+/// use axum_tracing_opentelemetry::tracing_subscriber_ext::build_otel_layer;
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), axum::BoxError> {
+/// let subscriber = tracing_subscriber::registry().with(build_otel_layer()?);
+/// tracing::subscriber::set_global_default(subscriber)?;
+/// # Ok(())
+/// # }
+/// ```
 pub fn build_otel_layer<S>() -> Result<OpenTelemetryLayer<S, Tracer>, BoxError>
 where
     S: Subscriber + for<'a> LookupSpan<'a>,
@@ -55,7 +228,6 @@ where
     use crate::{
         init_propagator, //stdio,
         otlp,
-        resource::DetectResource,
     };
     let otel_rsrc = DetectResource::default()
         //.with_fallback_service_name(env!("CARGO_PKG_NAME"))
@@ -69,21 +241,724 @@ where
     // let otel_tracer =
     //     stdio::init_tracer(otel_rsrc, stdio::identity, stdio::WriteNoWhere::default())?;
     init_propagator()?;
+    // the trace of the requests without remote parent is started by the same tracer
+    crate::middleware::set_default_tracer(&otel_tracer);
     Ok(tracing_opentelemetry::layer().with_tracer(otel_tracer))
 }
 
-pub fn init_subscribers() -> Result<(), BoxError> {
-    //setup a temporary subscriber to log output during setup
-    let subscriber = tracing_subscriber::registry()
-        .with(build_loglevel_filter_layer())
-        .with(build_logger_text());
-    let _guard = tracing::subscriber::set_default(subscriber);
-    info!("init logging & tracing");
-
-    let subscriber = tracing_subscriber::registry()
-        .with(build_otel_layer()?)
-        .with(build_loglevel_filter_layer())
-        .with(build_logger_text());
-    tracing::subscriber::set_global_default(subscriber)?;
-    Ok(())
+/// [logfmt](https://brandur.org/logfmt) format of the events (`key=value` separated by spaces,
+/// values quoted when needed), eg:
+///
+/// ```text
+/// ts=2023-03-21T10:15:42.123456Z level=info target=app span=request trace_id=4bf92f3577b34da6a3ce929d0e0e4736 span_id=00f067aa0ba902b7 msg="hello world" user.id=42
+/// ```
+///
+/// The fields of the spans are not printed, only the name of the current span
+/// and its `trace_id` & `span_id` (when created by the `OpenTelemetryLayer`).
+#[derive(Debug, Clone, Default)]
+pub struct Logfmt<T = SystemTime> {
+    timer: T,
+    tracer: Option<Tracer>,
+}
+
+impl Logfmt {
+    /// The logfmt format, with the system time (RFC 3339) as `ts`.
+    pub fn new() -> Self {
+        Logfmt::default()
+    }
+}
+
+impl<T> Logfmt<T> {
+    /// Use `timer` for the `ts` field.
+    pub fn with_timer<T2>(self, timer: T2) -> Logfmt<T2> {
+        Logfmt {
+            timer,
+            tracer: self.tracer,
+        }
+    }
+
+    /// Do not print the `ts` field.
+    pub fn without_time(self) -> Logfmt<()> {
+        self.with_timer(())
+    }
+
+    /// The tracer of the `OpenTelemetryLayer`, to sample the spans not sampled yet
+    /// (default: the tracer installed by [`init_subscribers`]).
+    pub fn with_tracer(mut self, tracer: Tracer) -> Self {
+        self.tracer = Some(tracer);
+        self
+    }
+}
+
+impl<S, N, T> FormatEvent<S, N> for Logfmt<T>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+    T: FormatTime,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let meta = event.metadata();
+        let mut ts = String::new();
+        self.timer.format_time(&mut Writer::new(&mut ts))?;
+        if !ts.is_empty() {
+            write!(writer, "ts=")?;
+            write_logfmt_value(&mut writer, &ts)?;
+            write!(writer, " ")?;
+        }
+        write!(
+            writer,
+            "level={} target=",
+            meta.level().as_str().to_ascii_lowercase()
+        )?;
+        write_logfmt_value(&mut writer, meta.target())?;
+        if let Some(span) = ctx.parent_span() {
+            write!(writer, " span=")?;
+            write_logfmt_value(&mut writer, span.name())?;
+            if let Some((trace_id, span_id, _)) = find_trace_context(&span, self.tracer.as_ref()) {
+                write!(writer, " trace_id={trace_id} span_id={span_id}")?;
+            }
+        }
+        let mut visitor = LogfmtVisitor {
+            writer: &mut writer,
+            result: Ok(()),
+        };
+        event.record(&mut visitor);
+        visitor.result?;
+        writeln!(writer)
+    }
+}
+
+struct LogfmtVisitor<'a, 'w> {
+    writer: &'a mut Writer<'w>,
+    result: fmt::Result,
+}
+
+impl Visit for LogfmtVisitor<'_, '_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if self.result.is_err() {
+            return;
+        }
+        let key = match field.name() {
+            "message" => "msg",
+            // metadata of the events from `log` (already in the metadata of the event)
+            name if name.starts_with("log.") => return,
+            name => name,
+        };
+        self.result =
+            write!(self.writer, " {key}=").and_then(|()| write_logfmt_value(self.writer, value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.record_str(field, &format!("{value:?}"));
+    }
+}
+
+fn write_logfmt_value(writer: &mut Writer<'_>, value: &str) -> fmt::Result {
+    let needs_quotes = value.is_empty()
+        || value
+            .chars()
+            .any(|c| c == ' ' || c == '=' || c == '"' || c.is_control());
+    if !needs_quotes {
+        return writer.write_str(value);
+    }
+    writer.write_char('"')?;
+    for c in value.chars() {
+        match c {
+            '"' => writer.write_str("\\\"")?,
+            '\\' => writer.write_str("\\\\")?,
+            '\n' => writer.write_str("\\n")?,
+            '\r' => writer.write_str("\\r")?,
+            '\t' => writer.write_str("\\t")?,
+            c => writer.write_char(c)?,
+        }
+    }
+    writer.write_char('"')
+}
+
+/// Layer exporting the events as OTLP log records when `OTEL_LOGS_EXPORTER=otlp`
+/// (`None` when unset or `none`), with the same resource than the tracer,
+/// see [`otlp_logs::init_layer`](crate::otlp_logs::init_layer) for the configuration.
+#[cfg(feature = "logs")]
+pub fn build_otel_logs_layer() -> Result<Option<crate::otlp_logs::OtlpLogsLayer>, BoxError> {
+    otel_logs_layer_from_env(|| DetectResource::default().build())
+}
+
+#[cfg(feature = "logs")]
+fn otel_logs_layer_from_env<F>(
+    resource: F,
+) -> Result<Option<crate::otlp_logs::OtlpLogsLayer>, BoxError>
+where
+    F: FnOnce() -> Resource,
+{
+    let exporter = std::env::var("OTEL_LOGS_EXPORTER").unwrap_or_default();
+    tracing::debug!(target: "otel::setup", OTEL_LOGS_EXPORTER = exporter);
+    match exporter.trim() {
+        "" | "none" => Ok(None),
+        "otlp" => Ok(Some(crate::otlp_logs::init_layer(resource())?)),
+        other => Err(format!(
+            "unsupported OTEL_LOGS_EXPORTER '{other}' (supported: 'otlp', 'none')"
+        )
+        .into()),
+    }
+}
+
+/// The meter provider exporting the metrics via OTLP when `OTEL_METRICS_EXPORTER=otlp`
+/// (`None` when unset or `none`), installed as global meter provider,
+/// see [`otlp::init_meter`](crate::otlp::init_meter) for the configuration.
+#[cfg(feature = "metrics")]
+fn otel_meter_from_env<F>(
+    resource: F,
+) -> Result<Option<opentelemetry::sdk::metrics::controllers::BasicController>, BoxError>
+where
+    F: FnOnce() -> Resource,
+{
+    let exporter = std::env::var("OTEL_METRICS_EXPORTER").unwrap_or_default();
+    tracing::debug!(target: "otel::setup", OTEL_METRICS_EXPORTER = exporter);
+    match exporter.trim() {
+        "" | "none" => Ok(None),
+        "otlp" => Ok(Some(crate::otlp::init_meter(
+            resource(),
+            crate::otlp::identity,
+        )?)),
+        other => Err(format!(
+            "unsupported OTEL_METRICS_EXPORTER '{other}' (supported: 'otlp', 'none')"
+        )
+        .into()),
+    }
+}
+
+/// The (`trace_id`, `span_id`, `trace_flags`) of the otel span of `span`
+/// (created by the `OpenTelemetryLayer`), resolved like `OpenTelemetrySpanExt::context` does:
+/// the sampling of the span is decided by `tracer` (default: the tracer installed by
+/// [`init_subscribers`]) and kept by the span, so the span is exported with the same decision.
+///
+/// Without a tracer, the ids are read from the span as is (the root spans are not sampled yet).
+pub(crate) fn find_trace_context<S>(
+    span: &SpanRef<'_, S>,
+    tracer: Option<&Tracer>,
+) -> Option<(TraceId, SpanId, TraceFlags)>
+where
+    S: for<'a> LookupSpan<'a>,
+{
+    let mut extensions = span.extensions_mut();
+    let otel_data = extensions.get_mut::<OtelData>()?;
+    let tracer = tracer
+        .cloned()
+        .or_else(crate::middleware::default_tracer)
+        .filter(|tracer| tracer.provider().is_some());
+    if let Some(tracer) = tracer {
+        let context = tracer.sampled_context(otel_data);
+        let span_context = context.span().span_context().clone();
+        return span_context.is_valid().then(|| {
+            (
+                span_context.trace_id(),
+                span_context.span_id(),
+                span_context.trace_flags(),
+            )
+        });
+    }
+    let span_id = otel_data.builder.span_id?;
+    let (trace_id, parent_flags) = if otel_data.parent_cx.has_active_span() {
+        let parent = otel_data.parent_cx.span();
+        let parent_context = parent.span_context();
+        (parent_context.trace_id(), parent_context.trace_flags())
+    } else {
+        (otel_data.builder.trace_id?, TraceFlags::default())
+    };
+    let flags = match &otel_data.builder.sampling_result {
+        Some(result) if result.decision == SamplingDecision::RecordAndSample => TraceFlags::SAMPLED,
+        Some(_) => TraceFlags::default(),
+        None => parent_flags,
+    };
+    Some((trace_id, span_id, flags))
+}
+
+/// Flush & shutdown the providers installed by [`init_subscribers`] when dropped (the tracer
+/// provider, and with the features `metrics` & `logs` the meter provider & the logs exporter),
+/// so keep it until the end of the program (eg `let _guard = init_subscribers()?;` in `main`).
+///
+/// The drop waits at most the flush timeout (see [`OtelGuard::with_flush_timeout`]), then the
+/// shutdown is abandoned: it goes on in a detached thread, until the end of the exports in
+/// progress or the drop of the tokio runtime (the logs exporter stops waiting at the timeout).
+#[derive(Debug)]
+#[must_use = "the providers are shut down when the guard is dropped"]
+pub struct OtelGuard {
+    flush_timeout: Duration,
+    #[cfg(feature = "metrics")]
+    meter: Option<opentelemetry::sdk::metrics::controllers::BasicController>,
+    #[cfg(feature = "logs")]
+    logs: Option<crate::otlp_logs::OtlpLogsLayer>,
+}
+
+impl OtelGuard {
+    /// The maximum duration to wait for the export of the remaining data on drop
+    /// (default: 5s), the data not exported in time are lost.
+    pub fn with_flush_timeout(mut self, flush_timeout: Duration) -> Self {
+        self.flush_timeout = flush_timeout;
+        self
+    }
+
+    /// Also stop `meter` on drop (eg the controller returned by
+    /// [`otlp::init_meter`](crate::otlp::init_meter)), so the last metrics are exported.
+    #[cfg(feature = "metrics")]
+    pub fn with_meter(
+        mut self,
+        meter: opentelemetry::sdk::metrics::controllers::BasicController,
+    ) -> Self {
+        self.meter = Some(meter);
+        self
+    }
+}
+
+impl Drop for OtelGuard {
+    fn drop(&mut self) {
+        #[cfg(feature = "metrics")]
+        let meter = self.meter.take();
+        #[cfg(feature = "logs")]
+        let logs = self.logs.take();
+        let flush_timeout = self.flush_timeout;
+        #[cfg(feature = "logs")]
+        let deadline = std::time::Instant::now() + flush_timeout;
+        let (sender, receiver) = std::sync::mpsc::channel();
+        // the shutdown blocks until the end of the export, that could need the current thread
+        // (eg with a `current_thread` tokio runtime), so it is done on a dedicated thread
+        std::thread::spawn(move || {
+            opentelemetry::global::shutdown_tracer_provider();
+            #[cfg(feature = "logs")]
+            if let Some(logs) = logs {
+                logs.shutdown_blocking(
+                    deadline.saturating_duration_since(std::time::Instant::now()),
+                );
+            }
+            #[cfg(feature = "metrics")]
+            if let Some(meter) = meter {
+                // collect & export the metrics one last time
+                if let Err(err) = meter.stop(&opentelemetry::Context::current()) {
+                    tracing::warn!("failed to stop the meter provider: {err}");
+                }
+            }
+            let _ = sender.send(());
+        });
+        if receiver.recv_timeout(flush_timeout).is_err() {
+            tracing::warn!(
+                "the flush & shutdown of the opentelemetry providers timed out after {:?}, \
+                abandoned",
+                flush_timeout
+            );
+        }
+    }
+}
+
+/// Very opinionated setup of the tracing subscriber (filter from `RUST_LOG`, logs printed on
+/// stdout in the format from `LOG_FORMAT`, traces exported to `OTEL_TRACES_EXPORTER`),
+/// use [`OtelSetup`] to customize it.
+pub fn init_subscribers() -> Result<OtelGuard, BoxError> {
+    OtelSetup::new().init()
+}
+
+/// The exporter of the traces of [`OtelSetup`], the exporters are configured by their
+/// environment variables (like with [`init_tracer_from_env`](crate::init_tracer_from_env)).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exporter {
+    /// OTLP (see [`otlp::init_tracer`](crate::otlp::init_tracer))
+    Otlp,
+    /// Jaeger agent (see [`jaeger::init_tracer`](crate::jaeger::init_tracer))
+    #[cfg(feature = "jaeger")]
+    Jaeger,
+    /// Zipkin (see [`zipkin::init_tracer`](crate::zipkin::init_tracer))
+    #[cfg(feature = "zipkin")]
+    Zipkin,
+    /// The spans printed on stdout (see [`stdio::init_tracer`](crate::stdio::init_tracer))
+    Stdout,
+    /// No export, the traces are still created & propagated
+    None,
+}
+
+impl Exporter {
+    fn collector_kind(self) -> crate::CollectorKind {
+        match self {
+            Exporter::Otlp => crate::CollectorKind::Otlp,
+            #[cfg(feature = "jaeger")]
+            Exporter::Jaeger => crate::CollectorKind::Jaeger,
+            #[cfg(feature = "zipkin")]
+            Exporter::Zipkin => crate::CollectorKind::Zipkin,
+            Exporter::Stdout => crate::CollectorKind::Stdout,
+            Exporter::None => crate::CollectorKind::NoWrite,
+        }
+    }
+}
+
+/// Builder of the setup of the tracing subscriber: the logs printed on stdout, the traces
+/// exported by the opentelemetry tracer (and the logs exported via OTLP with the feature `logs`
+/// when `OTEL_LOGS_EXPORTER=otlp`, the metrics with the feature `metrics` when
+/// `OTEL_METRICS_EXPORTER=otlp`).
+///
+/// The settings not defined use the same defaults (and environment variables) than
+/// [`init_subscribers`].
+///
+/// ```no_run
+/// use axum_tracing_opentelemetry::tracing_subscriber_ext::{Exporter, LogFormat, OtelSetup};
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), axum::BoxError> {
+/// let _guard = OtelSetup::new()
+///     .with_service_name(env!("CARGO_PKG_NAME"))
+///     .with_service_version(env!("CARGO_PKG_VERSION"))
+///     .with_exporter(Exporter::Stdout)
+///     .with_log_format(LogFormat::Compact)
+///     .with_filter("info,axum_tracing_opentelemetry=info,otel=debug")
+///     .with_propagators("tracecontext,baggage")
+///     .init()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct OtelSetup {
+    service_name: Option<&'static str>,
+    service_version: Option<&'static str>,
+    exporter: Option<Exporter>,
+    log_format: Option<LogFormat>,
+    filter: Option<String>,
+    propagators: Option<String>,
+    strict_sampler: bool,
+}
+
+impl OtelSetup {
+    /// The setup with every setting from the environment variables, like [`init_subscribers`].
+    ///
+    /// ```no_run
+    /// use axum_tracing_opentelemetry::tracing_subscriber_ext::OtelSetup;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), axum::BoxError> {
+    /// // same as `init_subscribers()?`
+    /// let _guard = OtelSetup::new().init()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The `service.name` of the resource, when not defined by the environment variables
+    /// (see [`DetectResource::with_fallback_service_name`]).
+    pub fn with_service_name(mut self, service_name: &'static str) -> Self {
+        self.service_name = Some(service_name);
+        self
+    }
+
+    /// The `service.version` of the resource, when not defined by the environment variables
+    /// (see [`DetectResource::with_fallback_service_version`]).
+    pub fn with_service_version(mut self, service_version: &'static str) -> Self {
+        self.service_version = Some(service_version);
+        self
+    }
+
+    /// Where to export the traces (default: from `OTEL_TRACES_EXPORTER`, see
+    /// [`init_tracer_from_env`](crate::init_tracer_from_env)), use [`Exporter::None`]
+    /// to only create & propagate the traces.
+    ///
+    /// ```no_run
+    /// use axum_tracing_opentelemetry::tracing_subscriber_ext::{Exporter, OtelSetup};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), axum::BoxError> {
+    /// let _guard = OtelSetup::new().with_exporter(Exporter::None).init()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_exporter(mut self, exporter: Exporter) -> Self {
+        self.exporter = Some(exporter);
+        self
+    }
+
+    /// The format of the logs printed on stdout (default: from `LOG_FORMAT`, see [`LogFormat`]).
+    pub fn with_log_format(mut self, log_format: LogFormat) -> Self {
+        self.log_format = Some(log_format);
+        self
+    }
+
+    /// The directives of the filter of the logs & traces, with the syntax of `RUST_LOG`
+    /// (default: [`build_loglevel_filter_layer`]), `axum_tracing_opentelemetry` should be
+    /// at level `info` (at least) to create the spans of the requests.
+    pub fn with_filter(mut self, directives: impl Into<String>) -> Self {
+        self.filter = Some(directives.into());
+        self
+    }
+
+    /// The propagators as a comma-separated list, with the same values than `OTEL_PROPAGATORS`
+    /// (default: from `OTEL_PROPAGATORS`, see [`init_propagator`](crate::init_propagator)).
+    pub fn with_propagators(mut self, propagators: impl Into<String>) -> Self {
+        self.propagators = Some(propagators.into());
+        self
+    }
+
+    /// Fail the setup on an invalid `OTEL_TRACES_SAMPLER` / `OTEL_TRACES_SAMPLER_ARG` (default:
+    /// `false`, a warning is logged and `parentbased_always_on` is used), see
+    /// [`read_sampler_from_env`](crate::sampler::read_sampler_from_env).
+    ///
+    /// ```no_run
+    /// use axum_tracing_opentelemetry::tracing_subscriber_ext::OtelSetup;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), axum::BoxError> {
+    /// let _guard = OtelSetup::new().with_strict_sampler(true).init()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_strict_sampler(mut self, strict: bool) -> Self {
+        self.strict_sampler = strict;
+        self
+    }
+
+    /// Install the tracer provider, the propagator and the global tracing subscriber.
+    pub fn init(self) -> Result<OtelGuard, BoxError> {
+        let log_format = self.log_format.unwrap_or_else(LogFormat::from_env);
+        //setup a temporary subscriber to log output during setup
+        let subscriber = tracing_subscriber::registry()
+            .with(self.build_filter())
+            .with(logger_text(log_format));
+        let _guard = tracing::subscriber::set_default(subscriber);
+        info!("init logging & tracing");
+
+        let resource = self.build_resource();
+        #[cfg(feature = "logs")]
+        let logs = otel_logs_layer_from_env(|| resource.clone())?;
+        #[cfg(feature = "metrics")]
+        let meter = otel_meter_from_env(|| resource.clone())?;
+        let kind = match self.exporter {
+            Some(exporter) => exporter.collector_kind(),
+            None => crate::collector_kind_from_env()?,
+        };
+        let tracer = crate::init_tracer_of_kind(
+            kind,
+            resource,
+            crate::sampler::SamplerConfig::from_env(),
+            self.strict_sampler,
+        )?;
+        match &self.propagators {
+            Some(propagators) => crate::init_propagator_from_str(propagators)?,
+            None => crate::init_propagator()?,
+        }
+        crate::middleware::set_default_tracer(&tracer);
+
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(tracer))
+            .with(self.build_filter())
+            .with(logger_text(log_format));
+        #[cfg(feature = "logs")]
+        let subscriber = subscriber.with(logs.clone());
+        tracing::subscriber::set_global_default(subscriber)?;
+        Ok(OtelGuard {
+            flush_timeout: Duration::from_secs(5),
+            #[cfg(feature = "metrics")]
+            meter,
+            #[cfg(feature = "logs")]
+            logs,
+        })
+    }
+
+    fn build_filter(&self) -> EnvFilter {
+        match &self.filter {
+            Some(directives) => EnvFilter::builder().parse_lossy(directives),
+            None => build_loglevel_filter_layer(),
+        }
+    }
+
+    fn build_resource(&self) -> Resource {
+        let mut detect_resource = DetectResource::default();
+        if let Some(service_name) = self.service_name {
+            detect_resource = detect_resource.with_fallback_service_name(service_name);
+        }
+        if let Some(service_version) = self.service_version {
+            detect_resource = detect_resource.with_fallback_service_version(service_version);
+        }
+        detect_resource.build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert2::{check, let_assert};
+    use opentelemetry::trace::TracerProvider as _;
+    use rstest::*;
+    use std::sync::{Arc, Mutex};
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    #[derive(Clone, Default)]
+    struct BufferWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for BufferWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn json_events_have_the_trace_context() {
+        // the provider should outlive the tracer (only weakly referenced by the tracer)
+        let provider = opentelemetry::sdk::trace::TracerProvider::builder().build();
+        let buffer = BufferWriter::default();
+        let writer = buffer.clone();
+        let tracer = provider.tracer("test");
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(tracer.clone()))
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .json()
+                    .event_format(
+                        JsonWithTraceContext::new(
+                            tracing_subscriber::fmt::format().json().without_time(),
+                        )
+                        .with_tracer(tracer),
+                    )
+                    .with_writer(move || writer.clone()),
+            );
+
+        let (root_context, child_context) = tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("outside of span");
+            // spans not sampled yet (nothing reads their context before the events)
+            let root = tracing::info_span!("root");
+            let _root = root.enter();
+            tracing::info!("inside root span");
+            let child = tracing::info_span!("child");
+            child.in_scope(|| tracing::info!("inside child span"));
+            (
+                root.context().span().span_context().clone(),
+                child.context().span().span_context().clone(),
+            )
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines = output
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        let_assert!([outside, inside_root, inside_child] = lines.as_slice());
+        check!(outside.get("trace_id") == None);
+        check!(outside["fields"]["message"] == "outside of span");
+        for (line, span_context) in [(inside_root, root_context), (inside_child, child_context)] {
+            check!(span_context.is_valid());
+            check!(line["trace_id"] == span_context.trace_id().to_string());
+            check!(line["span_id"] == span_context.span_id().to_string());
+            check!(line["trace_flags"] == "01");
+        }
+        check!(inside_root["fields"]["message"] == "inside root span");
+        check!(inside_child["fields"]["message"] == "inside child span");
+    }
+
+    #[rstest]
+    #[case(opentelemetry::sdk::trace::Sampler::AlwaysOn, "01")]
+    #[case(opentelemetry::sdk::trace::Sampler::AlwaysOff, "00")]
+    fn json_events_in_untouched_root_span_sampled_by_the_tracer(
+        #[case] sampler: opentelemetry::sdk::trace::Sampler,
+        #[case] expected_flags: &str,
+    ) {
+        let provider = opentelemetry::sdk::trace::TracerProvider::builder()
+            .with_config(opentelemetry::sdk::trace::config().with_sampler(sampler))
+            .build();
+        let tracer = provider.tracer("test");
+        let buffer = BufferWriter::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(tracer.clone()))
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .json()
+                    .event_format(
+                        JsonWithTraceContext::new(
+                            tracing_subscriber::fmt::format().json().without_time(),
+                        )
+                        .with_tracer(tracer),
+                    )
+                    .with_writer(move || writer.clone()),
+            );
+
+        let span_context = tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("job");
+            span.in_scope(|| tracing::info!("inside untouched root span"));
+            span.context().span().span_context().clone()
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let_assert!(Ok(line) = serde_json::from_str::<serde_json::Value>(output.trim()));
+        check!(line["trace_id"] == span_context.trace_id().to_string());
+        check!(line["span_id"] == span_context.span_id().to_string());
+        check!(line["trace_flags"] == expected_flags);
+    }
+
+    #[rstest]
+    #[case("json", Ok(LogFormat::Json))]
+    #[case(" Pretty ", Ok(LogFormat::Pretty))]
+    #[case("compact", Ok(LogFormat::Compact))]
+    #[case("LOGFMT", Ok(LogFormat::Logfmt))]
+    #[case("yaml", Err(()))]
+    fn test_log_format_from_str(#[case] value: &str, #[case] expected: Result<LogFormat, ()>) {
+        check!(LogFormat::from_str(value).map_err(|_| ()) == expected);
+    }
+
+    #[test]
+    fn logfmt_events() {
+        let provider = opentelemetry::sdk::trace::TracerProvider::builder().build();
+        let buffer = BufferWriter::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")))
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .event_format(Logfmt::new().without_time())
+                    .with_writer(move || writer.clone()),
+            );
+
+        let span_context = tracing::subscriber::with_default(subscriber, || {
+            tracing::warn!(target: "app", user_id = 42, empty = "", "outside of span");
+            let span = tracing::info_span!("request");
+            span.in_scope(|| tracing::info!(target: "app", quote = "say \"hi\"\n", "inside span"));
+            span.context().span().span_context().clone()
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+        let_assert!([outside, inside] = lines.as_slice());
+        check!(*outside == r#"level=warn target=app msg="outside of span" user_id=42 empty="""#);
+        let expected = format!(
+            r#"level=info target=app span=request trace_id={} span_id={} msg="inside span" quote="say \"hi\"\n""#,
+            span_context.trace_id(),
+            span_context.span_id()
+        );
+        check!(*inside == expected);
+    }
+
+    #[test]
+    fn otel_setup_resource_with_fallback_service_name() {
+        use opentelemetry_semantic_conventions as semcov;
+
+        let resource = OtelSetup::new()
+            .with_service_name("my-service")
+            .with_service_version("1.2.3")
+            .build_resource();
+        let is_unset = |keys: &[&str]| keys.iter().all(|key| std::env::var(key).is_err());
+        // unless defined by the environment variables
+        if is_unset(&["OTEL_SERVICE_NAME", "SERVICE_NAME", "APP_NAME"]) {
+            check!(
+                resource.get(semcov::resource::SERVICE_NAME)
+                    == Some("my-service".to_owned().into())
+            );
+        }
+        if is_unset(&["SERVICE_VERSION", "APP_VERSION"]) {
+            check!(
+                resource.get(semcov::resource::SERVICE_VERSION) == Some("1.2.3".to_owned().into())
+            );
+        }
+    }
 }
@@ -0,0 +1,831 @@
+//! AWS X-Ray centralized sampling.
+//!
+//! The sampling rules (and their targets) are defined in X-Ray and polled from the X-Ray daemon
+//! (or any proxy of the `GetSamplingRules` & `GetSamplingTargets` APIs), like the X-Ray SDKs do.
+//!
+//! A rule samples the matching spans:
+//!
+//! - up to a number of spans per second (the reservoir), the quota of each instance of the
+//!   service is assigned by X-Ray from the statistics reported via `GetSamplingTargets`
+//!   (until a quota is assigned, 1 span per second is "borrowed")
+//! - then a fixed rate of the remaining spans
+//!
+//! Until the rules are fetched (eg while the daemon is unreachable), the default rule of X-Ray
+//! is applied: 1 span per second and 5% of the remaining spans.
+
+use opentelemetry::sdk::trace::{IdGenerator, RandomIdGenerator, Sampler, ShouldSample};
+use opentelemetry::sdk::Resource;
+use opentelemetry::trace::{
+    Link, OrderMap, SamplingDecision, SamplingResult, SpanKind, TraceContextExt, TraceError,
+    TraceId, TraceState,
+};
+use opentelemetry::{Context, InstrumentationLibrary, Key, Value};
+use opentelemetry_semantic_conventions as semcov;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Create the X-Ray sampler (wrapped into a parent based sampler, the decision of the remote
+/// parent is kept) from the `OTEL_TRACES_SAMPLER_ARG`.
+///
+/// `arg` is a comma-separated list of `key=value`:
+///
+/// - `endpoint`: the url of the X-Ray daemon (default: `http://localhost:2000`)
+/// - `pollingIntervalMs`: the interval between two polls of the rules, in milliseconds
+///   (default: 300000)
+pub(crate) fn sampler_from_arg(arg: &str, resource: &Resource) -> Result<Sampler, TraceError> {
+    let mut builder = XraySampler::builder(resource);
+    for (key, value) in arg
+        .split(',')
+        .filter_map(|kv| kv.split_once('='))
+        .map(|(k, v)| (k.trim(), v.trim()))
+    {
+        match key {
+            "endpoint" => builder = builder.with_endpoint(value),
+            "pollingIntervalMs" => {
+                let millis = value.parse().map_err(|err| {
+                    TraceError::from(format!("invalid pollingIntervalMs '{value}': {err}"))
+                })?;
+                builder = builder.with_rules_interval(Duration::from_millis(millis));
+            }
+            _ => {
+                tracing::warn!(target: "otel::setup", "unknown key '{key}' in OTEL_TRACES_SAMPLER_ARG of xray")
+            }
+        }
+    }
+    Ok(Sampler::ParentBased(Box::new(builder.build()?)))
+}
+
+/// Builder of [`XraySampler`], see [`XraySampler::builder`].
+#[derive(Debug, Clone)]
+pub struct XraySamplerBuilder {
+    endpoint: String,
+    rules_interval: Duration,
+    targets_interval: Duration,
+    service_name: Option<String>,
+    service_type: Option<String>,
+    resource_arn: Option<String>,
+}
+
+impl XraySamplerBuilder {
+    /// The url of the X-Ray daemon (default: `http://localhost:2000`).
+    pub fn with_endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.endpoint = endpoint.into();
+        self
+    }
+
+    /// The interval between two polls of the rules (default: 5 minutes).
+    pub fn with_rules_interval(mut self, interval: Duration) -> Self {
+        self.rules_interval = interval;
+        self
+    }
+
+    /// The interval between two reports of the statistics (and polls of the targets), when
+    /// X-Ray doesn't request another interval (default: 10 seconds).
+    pub fn with_targets_interval(mut self, interval: Duration) -> Self {
+        self.targets_interval = interval;
+        self
+    }
+
+    /// Build the sampler, and spawn the polling of the rules & targets on the tokio runtime
+    /// (so should be called from the context of a tokio runtime).
+    ///
+    /// The polling stops when the sampler (and its clones) are dropped.
+    pub fn build(self) -> Result<XraySampler, TraceError> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .map_err(|err| TraceError::Other(Box::new(err)))?;
+        let client_id = format!("{:032x}", RandomIdGenerator::default().new_trace_id());
+        let inner = Arc::new(Inner {
+            client_id: client_id[..24].to_string(),
+            service_name: self.service_name,
+            service_type: self.service_type,
+            resource_arn: self.resource_arn,
+            rules: Mutex::new(Vec::new()),
+            fallback: Mutex::new(Rule::fallback()),
+        });
+        let poller = Poller {
+            inner: Arc::downgrade(&inner),
+            client,
+            endpoint: self.endpoint.trim_end_matches('/').to_string(),
+            rules_interval: self.rules_interval,
+            targets_interval: self.targets_interval,
+        };
+        tokio::spawn(poller.run());
+        Ok(XraySampler { inner })
+    }
+}
+
+/// A sampler applying the sampling rules of X-Ray, see the [module](self) documentation.
+#[derive(Debug, Clone)]
+pub struct XraySampler {
+    inner: Arc<Inner>,
+}
+
+impl XraySampler {
+    /// The rules are matched against the `service.name`, the `cloud.platform` (as service type)
+    /// and the `cloud.resource_id` (as resource ARN) of `resource`.
+    pub fn builder(resource: &Resource) -> XraySamplerBuilder {
+        XraySamplerBuilder {
+            endpoint: "http://localhost:2000".to_string(), //Devskim: ignore DS137138
+            rules_interval: Duration::from_secs(300),
+            targets_interval: Duration::from_secs(10),
+            service_name: resource
+                .get(semcov::resource::SERVICE_NAME)
+                .map(|v| v.to_string()),
+            service_type: resource
+                .get(semcov::resource::CLOUD_PLATFORM)
+                .and_then(|v| service_type(v.as_str().as_ref()))
+                .map(str::to_string),
+            resource_arn: resource
+                .get(Key::from_static_str("cloud.resource_id"))
+                .map(|v| v.to_string()),
+        }
+    }
+}
+
+impl ShouldSample for XraySampler {
+    fn should_sample(
+        &self,
+        parent_context: Option<&Context>,
+        trace_id: TraceId,
+        _name: &str,
+        _span_kind: &SpanKind,
+        attributes: &OrderMap<Key, Value>,
+        _links: &[Link],
+        _instrumentation_library: &InstrumentationLibrary,
+    ) -> SamplingResult {
+        let sampled = self
+            .inner
+            .should_sample(trace_id, attributes, SystemTime::now());
+        SamplingResult {
+            decision: if sampled {
+                SamplingDecision::RecordAndSample
+            } else {
+                SamplingDecision::Drop
+            },
+            attributes: Vec::new(),
+            trace_state: match parent_context {
+                Some(cx) => cx.span().span_context().trace_state().clone(),
+                None => TraceState::default(),
+            },
+        }
+    }
+}
+
+/// The service type of X-Ray for the `cloud.platform`.
+fn service_type(cloud_platform: &str) -> Option<&'static str> {
+    match cloud_platform {
+        "aws_ec2" => Some("AWS::EC2::Instance"),
+        "aws_ecs" => Some("AWS::ECS::Container"),
+        "aws_eks" => Some("AWS::EKS::Container"),
+        "aws_elastic_beanstalk" => Some("AWS::ElasticBeanstalk::Environment"),
+        "aws_lambda" => Some("AWS::Lambda::Function"),
+        _ => None,
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    client_id: String,
+    service_name: Option<String>,
+    service_type: Option<String>,
+    resource_arn: Option<String>,
+    /// sorted by priority (then by name)
+    rules: Mutex<Vec<Rule>>,
+    /// used until the rules are fetched
+    fallback: Mutex<Rule>,
+}
+
+impl Inner {
+    fn should_sample(
+        &self,
+        trace_id: TraceId,
+        attributes: &OrderMap<Key, Value>,
+        now: SystemTime,
+    ) -> bool {
+        let mut rules = self.rules.lock().unwrap_or_else(|err| err.into_inner());
+        match rules.iter_mut().find(|rule| rule.matches(self, attributes)) {
+            Some(rule) => rule.sample(trace_id, now),
+            None => self
+                .fallback
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .sample(trace_id, now),
+        }
+    }
+
+    fn update_rules(&self, records: Vec<SamplingRuleRecord>) {
+        let mut rules = self.rules.lock().unwrap_or_else(|err| err.into_inner());
+        let mut previous: HashMap<String, Rule> = rules
+            .drain(..)
+            .map(|r| (r.spec.rule_name.clone(), r))
+            .collect();
+        *rules = records
+            .into_iter()
+            .map(|record| record.sampling_rule)
+            .filter(|spec| spec.version == 1)
+            .map(|spec| match previous.remove(&spec.rule_name) {
+                // keep the quota & the statistics of the rule
+                Some(rule) if rule.spec == spec => rule,
+                _ => Rule::new(spec),
+            })
+            .collect();
+        rules.sort_by(|a, b| {
+            (a.spec.priority, &a.spec.rule_name).cmp(&(b.spec.priority, &b.spec.rule_name))
+        });
+    }
+
+    fn take_statistics(&self, now: SystemTime) -> Vec<SamplingStatisticsDocument> {
+        let timestamp = epoch_secs(now);
+        let mut rules = self.rules.lock().unwrap_or_else(|err| err.into_inner());
+        rules
+            .iter_mut()
+            .map(|rule| {
+                let statistics = std::mem::take(&mut rule.statistics);
+                SamplingStatisticsDocument {
+                    rule_name: rule.spec.rule_name.clone(),
+                    client_id: self.client_id.clone(),
+                    timestamp,
+                    request_count: statistics.request_count,
+                    sampled_count: statistics.sampled_count,
+                    borrow_count: statistics.borrow_count,
+                }
+            })
+            .collect()
+    }
+
+    fn update_targets(&self, targets: Vec<SamplingTargetDocument>) {
+        let mut rules = self.rules.lock().unwrap_or_else(|err| err.into_inner());
+        for target in targets {
+            if let Some(rule) = rules
+                .iter_mut()
+                .find(|rule| rule.spec.rule_name == target.rule_name)
+            {
+                if let Some(fixed_rate) = target.fixed_rate {
+                    rule.fixed_rate = fixed_rate;
+                }
+                if let Some(quota) = target.reservoir_quota {
+                    rule.reservoir.quota = quota;
+                    rule.reservoir.expire_at = target
+                        .reservoir_quota_ttl
+                        .and_then(|ttl| Duration::try_from_secs_f64(ttl).ok())
+                        .map(|ttl| UNIX_EPOCH + ttl);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Rule {
+    spec: SamplingRule,
+    /// the fixed rate of the rule, or of the last target
+    fixed_rate: f64,
+    reservoir: Reservoir,
+    statistics: Statistics,
+}
+
+impl Rule {
+    fn new(spec: SamplingRule) -> Self {
+        Rule {
+            fixed_rate: spec.fixed_rate,
+            reservoir: Reservoir::new(spec.reservoir_size),
+            statistics: Statistics::default(),
+            spec,
+        }
+    }
+
+    /// The `Default` rule of X-Ray: 1 span per second and 5% of the remaining spans.
+    fn fallback() -> Self {
+        Rule::new(SamplingRule {
+            rule_name: "Default".to_string(),
+            priority: 10000,
+            fixed_rate: 0.05,
+            reservoir_size: 1,
+            ..SamplingRule::default()
+        })
+    }
+
+    fn matches(&self, inner: &Inner, attributes: &OrderMap<Key, Value>) -> bool {
+        let attribute = |keys: &[&str]| {
+            keys.iter()
+                .find_map(|key| attributes.get(&Key::from(key.to_string())))
+                .map(|v| v.as_str().into_owned())
+        };
+        let spec = &self.spec;
+        matches_pattern(&spec.service_name, inner.service_name.as_deref())
+            && matches_pattern(&spec.service_type, inner.service_type.as_deref())
+            && matches_pattern(&spec.resource_arn, inner.resource_arn.as_deref())
+            && matches_pattern(
+                &spec.host,
+                attribute(&["http.host", "server.address", "net.host.name"]).as_deref(),
+            )
+            && matches_pattern(
+                &spec.http_method,
+                attribute(&["http.method", "http.request.method"]).as_deref(),
+            )
+            && matches_pattern(
+                &spec.url_path,
+                attribute(&["url.path", "http.target"]).as_deref(),
+            )
+            && spec.attributes.iter().all(|(key, pattern)| {
+                matches_pattern(pattern, attribute(&[key.as_str()]).as_deref())
+            })
+    }
+
+    fn sample(&mut self, trace_id: TraceId, now: SystemTime) -> bool {
+        self.statistics.request_count += 1;
+        let sampled = match self.reservoir.take(now) {
+            Some(Take::Quota) => true,
+            Some(Take::Borrow) => {
+                self.statistics.borrow_count += 1;
+                true
+            }
+            None => sampled_by_ratio(trace_id, self.fixed_rate),
+        };
+        if sampled {
+            self.statistics.sampled_count += 1;
+        }
+        sampled
+    }
+}
+
+/// The spans per second of a rule.
+#[derive(Debug)]
+struct Reservoir {
+    size: u64,
+    /// the quota assigned by X-Ray, valid until `expire_at`
+    quota: u64,
+    expire_at: Option<SystemTime>,
+    /// the spans taken during the current second
+    current_second: u64,
+    taken: u64,
+}
+
+enum Take {
+    Quota,
+    Borrow,
+}
+
+impl Reservoir {
+    fn new(size: u64) -> Self {
+        Reservoir {
+            size,
+            quota: 0,
+            expire_at: None,
+            current_second: 0,
+            taken: 0,
+        }
+    }
+
+    fn take(&mut self, now: SystemTime) -> Option<Take> {
+        let second = epoch_secs(now) as u64;
+        if second != self.current_second {
+            self.current_second = second;
+            self.taken = 0;
+        }
+        match self.expire_at {
+            Some(expire_at) if now < expire_at => (self.taken < self.quota).then(|| {
+                self.taken += 1;
+                Take::Quota
+            }),
+            // no (valid) quota assigned, borrow 1 span per second
+            _ => (self.size > 0 && self.taken < 1).then(|| {
+                self.taken += 1;
+                Take::Borrow
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Statistics {
+    request_count: u64,
+    sampled_count: u64,
+    borrow_count: u64,
+}
+
+/// Same computation than the `TraceIdRatioBased` sampler (the X-Ray id generator prefixes the
+/// trace id with the timestamp, so only the lower bytes are used).
+fn sampled_by_ratio(trace_id: TraceId, ratio: f64) -> bool {
+    let bytes = trace_id.to_bytes();
+    let (_, low) = bytes.split_at(8);
+    let trace_id_low = u64::from_be_bytes(low.try_into().unwrap_or_default());
+    let rnd_from_trace_id = trace_id_low >> 1;
+    let prob_upper_bound = (ratio.max(0.0) * (1u64 << 63) as f64) as u64;
+    rnd_from_trace_id < prob_upper_bound
+}
+
+fn epoch_secs(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+/// Match `value` against `pattern` (case insensitive), where `*` matches any sequence of
+/// characters and `?` any character. A missing value only matches `*`.
+fn matches_pattern(pattern: &str, value: Option<&str>) -> bool {
+    if pattern == "*" {
+        return true;
+    }
+    let Some(value) = value else {
+        return false;
+    };
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let value: Vec<char> = value.to_lowercase().chars().collect();
+    let (mut p, mut v) = (0, 0);
+    // the position of the last `*` in the pattern, and of the value when it was reached
+    let mut star = None;
+    while v < value.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == value[v]) {
+            p += 1;
+            v += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, v));
+            p += 1;
+        } else if let Some((star_p, star_v)) = star {
+            // let the `*` match one more character
+            star = Some((star_p, star_v + 1));
+            p = star_p + 1;
+            v = star_v + 1;
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+struct Poller {
+    inner: Weak<Inner>,
+    client: reqwest::Client,
+    endpoint: String,
+    rules_interval: Duration,
+    targets_interval: Duration,
+}
+
+impl Poller {
+    async fn run(self) {
+        let mut next_rules = tokio::time::Instant::now();
+        let mut next_targets = next_rules + self.targets_interval;
+        let mut rules_fetched_at = SystemTime::UNIX_EPOCH;
+        loop {
+            tokio::time::sleep_until(next_rules.min(next_targets)).await;
+            let Some(inner) = self.inner.upgrade() else {
+                break;
+            };
+            let now = tokio::time::Instant::now();
+            if now >= next_rules {
+                next_rules = now + self.rules_interval;
+                match self.fetch_rules().await {
+                    Ok(records) => {
+                        inner.update_rules(records);
+                        rules_fetched_at = SystemTime::now();
+                    }
+                    Err(err) => tracing::warn!("failed to fetch the X-Ray sampling rules: {err}"),
+                }
+            }
+            if now >= next_targets {
+                next_targets = now + self.targets_interval;
+                let statistics = inner.take_statistics(SystemTime::now());
+                if statistics.is_empty() {
+                    continue;
+                }
+                match self.fetch_targets(statistics).await {
+                    Ok(output) => {
+                        if let Some(interval) = output
+                            .sampling_target_documents
+                            .iter()
+                            .filter_map(|target| target.interval)
+                            .min()
+                        {
+                            next_targets = now + Duration::from_secs(interval);
+                        }
+                        inner.update_targets(output.sampling_target_documents);
+                        if output
+                            .last_rule_modification
+                            .is_some_and(|at| at > epoch_secs(rules_fetched_at))
+                        {
+                            next_rules = now;
+                        }
+                    }
+                    Err(err) => tracing::warn!("failed to fetch the X-Ray sampling targets: {err}"),
+                }
+            }
+        }
+    }
+
+    async fn fetch_rules(&self) -> Result<Vec<SamplingRuleRecord>, TraceError> {
+        let mut records = Vec::new();
+        let mut next_token = None;
+        loop {
+            let output: GetSamplingRulesOutput = self
+                .post("GetSamplingRules", &GetSamplingRulesInput { next_token })
+                .await?;
+            records.extend(output.sampling_rule_records);
+            next_token = output.next_token;
+            if next_token.is_none() {
+                return Ok(records);
+            }
+        }
+    }
+
+    async fn fetch_targets(
+        &self,
+        statistics: Vec<SamplingStatisticsDocument>,
+    ) -> Result<GetSamplingTargetsOutput, TraceError> {
+        self.post(
+            "SamplingTargets",
+            &GetSamplingTargetsInput {
+                sampling_statistics_documents: statistics,
+            },
+        )
+        .await
+    }
+
+    async fn post<I, O>(&self, path: &str, input: &I) -> Result<O, TraceError>
+    where
+        I: Serialize,
+        O: for<'de> Deserialize<'de>,
+    {
+        let body = serde_json::to_vec(input).map_err(|err| TraceError::Other(Box::new(err)))?;
+        let response = self
+            .client
+            .post(format!("{}/{path}", self.endpoint))
+            .header("Content-Type", "application/json")
+            .body(body)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(|err| TraceError::Other(Box::new(err)))?;
+        let body = response
+            .bytes()
+            .await
+            .map_err(|err| TraceError::Other(Box::new(err)))?;
+        serde_json::from_slice(&body).map_err(|err| TraceError::Other(Box::new(err)))
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct GetSamplingRulesInput {
+    next_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct GetSamplingRulesOutput {
+    #[serde(default)]
+    sampling_rule_records: Vec<SamplingRuleRecord>,
+    next_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SamplingRuleRecord {
+    sampling_rule: SamplingRule,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "PascalCase", default)]
+struct SamplingRule {
+    rule_name: String,
+    priority: i64,
+    fixed_rate: f64,
+    reservoir_size: u64,
+    service_name: String,
+    service_type: String,
+    host: String,
+    #[serde(rename = "HTTPMethod")]
+    http_method: String,
+    #[serde(rename = "URLPath")]
+    url_path: String,
+    #[serde(rename = "ResourceARN")]
+    resource_arn: String,
+    attributes: HashMap<String, String>,
+    version: i64,
+}
+
+impl Default for SamplingRule {
+    fn default() -> Self {
+        SamplingRule {
+            rule_name: String::new(),
+            priority: 0,
+            fixed_rate: 0.0,
+            reservoir_size: 0,
+            service_name: "*".to_string(),
+            service_type: "*".to_string(),
+            host: "*".to_string(),
+            http_method: "*".to_string(),
+            url_path: "*".to_string(),
+            resource_arn: "*".to_string(),
+            attributes: HashMap::new(),
+            version: 1,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct GetSamplingTargetsInput {
+    sampling_statistics_documents: Vec<SamplingStatisticsDocument>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SamplingStatisticsDocument {
+    rule_name: String,
+    #[serde(rename = "ClientID")]
+    client_id: String,
+    timestamp: f64,
+    request_count: u64,
+    sampled_count: u64,
+    borrow_count: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct GetSamplingTargetsOutput {
+    #[serde(default)]
+    sampling_target_documents: Vec<SamplingTargetDocument>,
+    last_rule_modification: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SamplingTargetDocument {
+    rule_name: String,
+    fixed_rate: Option<f64>,
+    reservoir_quota: Option<u64>,
+    #[serde(rename = "ReservoirQuotaTTL")]
+    reservoir_quota_ttl: Option<f64>,
+    interval: Option<u64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert2::{check, let_assert};
+    use axum::{extract::State, routing::post, Json, Router};
+    use opentelemetry::trace::{Span as _, Tracer as _, TracerProvider as _};
+    use rstest::rstest;
+    use serde_json::{json, Value as JsonValue};
+
+    /// A trace id only sampled by a fixed rate of 1.0
+    const TRACE_ID: TraceId = TraceId::from_bytes([0xff; 16]);
+
+    fn url_path(path: &str) -> OrderMap<Key, Value> {
+        [(
+            Key::from_static_str("url.path"),
+            Value::from(path.to_string()),
+        )]
+        .into_iter()
+        .collect()
+    }
+
+    #[rstest]
+    #[case("*", None, true)]
+    #[case("*", Some(""), true)]
+    #[case("/hello", None, false)]
+    #[case("/hello", Some("/hello"), true)]
+    #[case("/hello", Some("/HELLO"), true)]
+    #[case("/hello", Some("/hello/world"), false)]
+    #[case("/hello*", Some("/hello/world"), true)]
+    #[case("/*/world", Some("/hello/world"), true)]
+    #[case("/*/world", Some("/hello/worlds"), false)]
+    #[case("/hell?", Some("/hello"), true)]
+    #[case("/hell?", Some("/hell"), false)]
+    #[case("*.example.com", Some("api.example.com"), true)]
+    fn test_matches_pattern(
+        #[case] pattern: &str,
+        #[case] value: Option<&str>,
+        #[case] expected: bool,
+    ) {
+        check!(matches_pattern(pattern, value) == expected);
+    }
+
+    #[test]
+    fn fallback_borrow_one_span_per_second() {
+        let mut rule = Rule::fallback();
+        let now = UNIX_EPOCH + Duration::from_secs(1_000);
+        check!(rule.sample(TRACE_ID, now));
+        check!(!rule.sample(TRACE_ID, now));
+        check!(!rule.sample(TRACE_ID, now + Duration::from_millis(500)));
+        check!(rule.sample(TRACE_ID, now + Duration::from_secs(1)));
+        check!(rule.statistics.request_count == 4);
+        check!(rule.statistics.sampled_count == 2);
+        check!(rule.statistics.borrow_count == 2);
+    }
+
+    #[test]
+    fn reservoir_take_the_quota_until_its_expiration() {
+        let mut rule = Rule::new(SamplingRule {
+            reservoir_size: 10,
+            ..SamplingRule::default()
+        });
+        let now = UNIX_EPOCH + Duration::from_secs(1_000);
+        rule.reservoir.quota = 2;
+        rule.reservoir.expire_at = Some(now + Duration::from_secs(10));
+        check!(rule.sample(TRACE_ID, now));
+        check!(rule.sample(TRACE_ID, now));
+        check!(!rule.sample(TRACE_ID, now));
+        check!(rule.statistics.borrow_count == 0);
+        // expired, fallback to borrowing
+        check!(rule.sample(TRACE_ID, now + Duration::from_secs(10)));
+        check!(!rule.sample(TRACE_ID, now + Duration::from_secs(10)));
+        check!(rule.statistics.borrow_count == 1);
+    }
+
+    #[tokio::test]
+    async fn apply_the_rules_and_the_targets_of_the_daemon() {
+        // a stand-in of the X-Ray daemon, recording the reported statistics
+        let statistics = Arc::new(Mutex::new(Vec::<JsonValue>::new()));
+        let daemon = Router::new()
+            .route(
+                "/GetSamplingRules",
+                post(|| async {
+                    Json(json!({"SamplingRuleRecords": [
+                        {"SamplingRule": {
+                            "RuleName": "hello", "Priority": 1, "FixedRate": 1.0, "ReservoirSize": 0,
+                            "ServiceName": "test", "ServiceType": "*", "Host": "*", "HTTPMethod": "*",
+                            "URLPath": "/hello*", "ResourceARN": "*", "Version": 1
+                        }},
+                        {"SamplingRule": {
+                            "RuleName": "Default", "Priority": 10000, "FixedRate": 0.0,
+                            "ReservoirSize": 0, "ServiceName": "*", "ServiceType": "*", "Host": "*",
+                            "HTTPMethod": "*", "URLPath": "*", "ResourceARN": "*", "Version": 1
+                        }}
+                    ]}))
+                }),
+            )
+            .route(
+                "/SamplingTargets",
+                post(
+                    |State(statistics): State<Arc<Mutex<Vec<JsonValue>>>>,
+                     Json(input): Json<JsonValue>| async move {
+                        statistics.lock().unwrap().push(input);
+                        Json(json!({"SamplingTargetDocuments": [
+                            {"RuleName": "Default", "FixedRate": 1.0, "Interval": 10}
+                        ]}))
+                    },
+                ),
+            )
+            .with_state(statistics.clone());
+        let server =
+            axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(daemon.into_make_service());
+        let endpoint = format!("http://{}", server.local_addr()); //Devskim: ignore DS137138
+        tokio::spawn(server);
+
+        let resource = Resource::new(vec![semcov::resource::SERVICE_NAME.string("test")]);
+        let sampler = XraySampler::builder(&resource)
+            .with_endpoint(endpoint)
+            .with_targets_interval(Duration::from_millis(50))
+            .build()
+            .unwrap();
+        let is_sampled = |path: &str| {
+            sampler
+                .inner
+                .should_sample(TRACE_ID, &url_path(path), SystemTime::now())
+        };
+        let mut rules_applied = false;
+        let mut targets_applied = false;
+        for _ in 0..500 {
+            // the fallback rule samples at most 1 span per second
+            rules_applied = rules_applied || (is_sampled("/hello") && is_sampled("/hello/world"));
+            targets_applied = is_sampled("/world") && is_sampled("/world");
+            if rules_applied && targets_applied {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        check!(rules_applied);
+        check!(targets_applied);
+
+        let statistics = statistics.lock().unwrap();
+        let_assert!(Some(input) = statistics.first());
+        let_assert!(Some(documents) = input["SamplingStatisticsDocuments"].as_array());
+        check!(documents.len() == 2);
+        let_assert!(Some(document) = documents.iter().find(|d| d["RuleName"] == "Default"));
+        check!(document["ClientID"].as_str().map(str::len) == Some(24));
+        check!(document["RequestCount"].as_u64() >= Some(1));
+        check!(document["BorrowCount"] == 0);
+    }
+
+    #[test]
+    fn trace_config_install_the_xray_id_generator() {
+        let provider = opentelemetry::sdk::trace::TracerProvider::builder()
+            .with_config(crate::tools::trace_config(
+                Resource::empty(),
+                Sampler::AlwaysOn,
+            ))
+            .build();
+        let span = provider.tracer("test").start("hello");
+        let trace_id = span.span_context().trace_id().to_bytes();
+        // the trace id starts with the epoch time (in seconds)
+        let_assert!(Ok(epoch) = <[u8; 4]>::try_from(&trace_id[..4]));
+        let elapsed = epoch_secs(SystemTime::now()) - f64::from(u32::from_be_bytes(epoch));
+        check!((0.0..10.0).contains(&elapsed));
+    }
+}
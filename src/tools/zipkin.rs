@@ -0,0 +1,132 @@
+use opentelemetry::sdk::resource::{ResourceDetector, SdkProvidedResourceDetector};
+use opentelemetry::sdk::Resource;
+use opentelemetry::{sdk::trace as sdktrace, trace::TraceError};
+use opentelemetry_semantic_conventions as semcov;
+use opentelemetry_zipkin::ZipkinPipelineBuilder;
+use std::time::Duration;
+
+use super::sampler::read_sampler_from_env;
+
+pub fn identity(v: ZipkinPipelineBuilder) -> ZipkinPipelineBuilder {
+    v
+}
+
+/// Setup a zipkin pipeline (spans exported by batch via http) with the service name.
+///
+/// Configured via the environment variables (see the
+/// [zipkin variables spec](https://opentelemetry.io/docs/reference/specification/sdk-environment-variables/#zipkin-exporter)):
+///
+/// - `OTEL_EXPORTER_ZIPKIN_ENDPOINT`: the url of the collector
+///   (default: `http://localhost:9411/api/v2/spans`)
+/// - `OTEL_EXPORTER_ZIPKIN_TIMEOUT`: the maximum duration of an export, in milliseconds
+///   (default: 10000)
+pub fn init_tracer<F>(resource: Resource, transform: F) -> Result<sdktrace::Tracer, TraceError>
+where
+    F: FnOnce(ZipkinPipelineBuilder) -> ZipkinPipelineBuilder,
+{
+    let sampler = read_sampler_from_env(&resource, false)?;
+    init_tracer_with_sampler(resource, transform, sampler)
+}
+
+/// Like [`init_tracer`], with the `sampler` instead of the one from `OTEL_TRACES_SAMPLER`.
+pub fn init_tracer_with_sampler<F>(
+    resource: Resource,
+    transform: F,
+    sampler: sdktrace::Sampler,
+) -> Result<sdktrace::Tracer, TraceError>
+where
+    F: FnOnce(ZipkinPipelineBuilder) -> ZipkinPipelineBuilder,
+{
+    let endpoint = std::env::var("OTEL_EXPORTER_ZIPKIN_ENDPOINT")
+        .ok()
+        .filter(|v| !v.trim().is_empty())
+        .unwrap_or_else(|| "http://localhost:9411/api/v2/spans".to_string()); //Devskim: ignore DS137138
+    tracing::debug!(target: "otel::setup", OTEL_EXPORTER_ZIPKIN_ENDPOINT = endpoint);
+    let timeout = std::env::var("OTEL_EXPORTER_ZIPKIN_TIMEOUT")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(10_000);
+    tracing::debug!(target: "otel::setup", OTEL_EXPORTER_ZIPKIN_TIMEOUT = timeout);
+    // an async client, a blocking client can not be used from the tokio runtime
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_millis(timeout))
+        .build()
+        .map_err(|err| TraceError::Other(Box::new(err)))?;
+
+    let mut pipeline = opentelemetry_zipkin::new_pipeline()
+        .with_collector_endpoint(endpoint)
+        .with_http_client(client);
+    // without service name, the zipkin pipeline ignores the trace config (and the sampler)
+    let service_name = resource
+        .get(semcov::resource::SERVICE_NAME)
+        .or_else(|| {
+            SdkProvidedResourceDetector
+                .detect(Duration::ZERO)
+                .get(semcov::resource::SERVICE_NAME)
+        })
+        .map(|name| name.to_string())
+        .unwrap_or_else(|| "unknown_service".to_string());
+    pipeline = pipeline.with_service_name(service_name);
+    pipeline = pipeline.with_trace_config(super::trace_config(resource, sampler));
+    pipeline = transform(pipeline);
+    pipeline.install_batch(opentelemetry::runtime::Tokio)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::SamplerConfig;
+    use assert2::{check, let_assert};
+    use axum::{extract::State, routing::post, Json, Router};
+    use opentelemetry::trace::Tracer as _;
+    use serde_json::Value;
+    use std::sync::{Arc, Mutex};
+
+    #[tokio::test]
+    async fn install_the_sampler() {
+        let sampler = SamplerConfig::TraceIdRatio(0.25)
+            .build(&Resource::empty())
+            .unwrap();
+        let tracer = init_tracer_with_sampler(Resource::empty(), identity, sampler).unwrap();
+        let_assert!(Some(provider) = tracer.provider());
+        check!(format!("{:?}", provider.config().sampler) == "TraceIdRatioBased(0.25)");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn export_spans_to_the_collector() {
+        // a stand-in of the zipkin collector, recording the posted spans
+        let posted = Arc::new(Mutex::new(Vec::<Value>::new()));
+        let collector = Router::new()
+            .route(
+                "/api/v2/spans",
+                post(
+                    |State(posted): State<Arc<Mutex<Vec<Value>>>>,
+                     Json(spans): Json<Vec<Value>>| async move {
+                        posted.lock().unwrap().extend(spans);
+                    },
+                ),
+            )
+            .with_state(posted.clone());
+        let server = axum::Server::bind(&"127.0.0.1:0".parse().unwrap())
+            .serve(collector.into_make_service());
+        let endpoint = format!("http://{}/api/v2/spans", server.local_addr()); //Devskim: ignore DS137138
+        tokio::spawn(server);
+
+        let resource = Resource::new(vec![semcov::resource::SERVICE_NAME.string("zipkin-test")]);
+        let tracer = init_tracer(resource, |pipeline| {
+            pipeline.with_collector_endpoint(endpoint)
+        })
+        .unwrap();
+        // keep the provider (the global provider could be replaced by other tests)
+        let_assert!(Some(provider) = tracer.provider());
+        tracer.in_span("hello", |_cx| {});
+        tokio::task::spawn_blocking(move || provider.force_flush())
+            .await
+            .unwrap();
+
+        let posted = posted.lock().unwrap();
+        let_assert!([span] = posted.as_slice());
+        check!(span["name"] == "hello");
+        check!(span["localEndpoint"]["serviceName"] == "zipkin-test");
+    }
+}